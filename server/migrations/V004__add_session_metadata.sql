@@ -0,0 +1,8 @@
+ALTER TABLE sessions
+    ADD COLUMN public_id    uuid NOT NULL UNIQUE DEFAULT uuid_generate_v4(),
+    ADD COLUMN created_at   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN ip           TEXT DEFAULT NULL,
+    ADD COLUMN user_agent   TEXT DEFAULT NULL;
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,11 @@
+ALTER TABLE users
+    ADD COLUMN totp_secret      TEXT DEFAULT NULL,
+    ADD COLUMN totp_enabled     BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN totp_last_step   BIGINT DEFAULT NULL;
+
+CREATE TABLE recovery_codes (
+    user_id     uuid REFERENCES users (user_id) ON DELETE CASCADE NOT NULL,
+    code_hash   TEXT NOT NULL,
+    used_at     TIMESTAMP WITH TIME ZONE DEFAULT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,11 @@
+CREATE TABLE webauthn_credentials (
+    credential_id   BYTEA PRIMARY KEY,
+    user_id         uuid REFERENCES users (user_id) ON DELETE CASCADE NOT NULL,
+    name            TEXT NOT NULL,
+    passkey         JSONB NOT NULL,
+    counter         BIGINT NOT NULL DEFAULT 0,
+    created_at      TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at    TIMESTAMP WITH TIME ZONE DEFAULT NULL
+);
+
+CREATE INDEX webauthn_credentials_user_id_idx ON webauthn_credentials (user_id);
@@ -0,0 +1,22 @@
+CREATE TABLE password_reset_tokens (
+    token_hash  TEXT PRIMARY KEY,
+    user_id     uuid REFERENCES users (user_id) ON DELETE CASCADE NOT NULL,
+    created_at  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at  TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at     TIMESTAMP WITH TIME ZONE DEFAULT NULL
+);
+
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id);
+
+CREATE TABLE email_outbox (
+    email_id    uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+    recipient   TEXT NOT NULL,
+    subject     TEXT NOT NULL,
+    body        TEXT NOT NULL,
+    created_at  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    sent_at     TIMESTAMP WITH TIME ZONE DEFAULT NULL,
+    attempts    INT NOT NULL DEFAULT 0,
+    last_error  TEXT DEFAULT NULL
+);
+
+CREATE INDEX email_outbox_pending_idx ON email_outbox (created_at) WHERE sent_at IS NULL;
@@ -0,0 +1,9 @@
+CREATE TABLE login_attempts (
+    -- Either 'account' (keyed by email) or 'ip'
+    key_type        TEXT NOT NULL,
+    key             TEXT NOT NULL,
+    failures        INT NOT NULL DEFAULT 0,
+    last_failure_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    locked_until    TIMESTAMP WITH TIME ZONE DEFAULT NULL,
+    PRIMARY KEY (key_type, key)
+);
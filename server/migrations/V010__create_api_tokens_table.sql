@@ -0,0 +1,13 @@
+CREATE TABLE api_tokens (
+    token_id        uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id         uuid REFERENCES users (user_id) ON DELETE CASCADE NOT NULL,
+    name            TEXT NOT NULL,
+    -- sha256 of the token. The token itself is only shown once when it is created.
+    token_hash      TEXT NOT NULL UNIQUE,
+    scopes          TEXT[] NOT NULL,
+    created_at      TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at      TIMESTAMP WITH TIME ZONE DEFAULT NULL,
+    last_used_at    TIMESTAMP WITH TIME ZONE DEFAULT NULL
+);
+
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
@@ -0,0 +1,25 @@
+CREATE TABLE roles (
+    role        TEXT PRIMARY KEY
+);
+
+CREATE TABLE permissions (
+    permission  TEXT PRIMARY KEY
+);
+
+CREATE TABLE role_permissions (
+    role        TEXT REFERENCES roles (role) ON DELETE CASCADE NOT NULL,
+    permission  TEXT REFERENCES permissions (permission) ON DELETE CASCADE NOT NULL,
+    PRIMARY KEY (role, permission)
+);
+
+INSERT INTO roles (role) VALUES ('user'), ('moderator'), ('admin');
+INSERT INTO permissions (permission) VALUES ('delete_post'), ('clear_lockouts'), ('manage_roles');
+INSERT INTO role_permissions (role, permission) VALUES
+    ('moderator', 'delete_post'),
+    ('admin', 'delete_post'),
+    ('admin', 'clear_lockouts'),
+    ('admin', 'manage_roles');
+
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user' REFERENCES roles (role);
+UPDATE users SET role='admin' WHERE admin;
+ALTER TABLE users DROP COLUMN admin;
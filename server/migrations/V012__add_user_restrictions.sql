@@ -0,0 +1,11 @@
+ALTER TABLE users
+    -- Suspended users cannot log in or post until this time
+    ADD COLUMN suspended_until      TIMESTAMP WITH TIME ZONE DEFAULT NULL,
+    ADD COLUMN banned               BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Reason and the acting user of the latest suspension or ban
+    ADD COLUMN restriction_reason   TEXT DEFAULT NULL,
+    ADD COLUMN restricted_by        uuid REFERENCES users (user_id) ON DELETE SET NULL DEFAULT NULL,
+    ADD COLUMN restricted_at        TIMESTAMP WITH TIME ZONE DEFAULT NULL;
+
+INSERT INTO permissions (permission) VALUES ('restrict_users');
+INSERT INTO role_permissions (role, permission) VALUES ('admin', 'restrict_users');
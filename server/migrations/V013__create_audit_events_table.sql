@@ -0,0 +1,31 @@
+-- Users are not referenced with foreign keys, so that events are kept after the users are deleted
+CREATE TABLE audit_events (
+    event_id    BIGSERIAL PRIMARY KEY,
+    event_type  TEXT NOT NULL,
+    -- User who did the action. NULL if the user was not logged in.
+    actor_id    uuid DEFAULT NULL,
+    -- User the action was done to
+    target_id   uuid DEFAULT NULL,
+    ip          TEXT DEFAULT NULL,
+    details     JSONB NOT NULL DEFAULT '{}',
+    created_at  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX audit_events_event_type_idx ON audit_events (event_type, event_id);
+CREATE INDEX audit_events_actor_id_idx ON audit_events (actor_id, event_id);
+CREATE INDEX audit_events_target_id_idx ON audit_events (target_id, event_id);
+CREATE INDEX audit_events_created_at_idx ON audit_events (created_at);
+
+-- The log is append-only
+CREATE FUNCTION audit_events_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE OR TRUNCATE ON audit_events
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_events_append_only();
+
+INSERT INTO permissions (permission) VALUES ('view_audit_log');
+INSERT INTO role_permissions (role, permission) VALUES ('admin', 'view_audit_log');
@@ -0,0 +1,19 @@
+ALTER TABLE posts ADD COLUMN edited_at TIMESTAMP WITH TIME ZONE DEFAULT NULL;
+
+-- Previous versions of edited posts
+CREATE TABLE post_revisions (
+    revision_id BIGSERIAL PRIMARY KEY,
+    post_id     uuid REFERENCES posts (post_id) ON DELETE CASCADE NOT NULL,
+    text        TEXT NOT NULL,
+    -- When this version was written
+    created_at  TIMESTAMP WITH TIME ZONE NOT NULL,
+    -- When this version was replaced by an edit
+    replaced_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX post_revisions_post_id_idx ON post_revisions (post_id, revision_id);
+
+INSERT INTO permissions (permission) VALUES ('view_post_revisions');
+INSERT INTO role_permissions (role, permission) VALUES
+    ('moderator', 'view_post_revisions'),
+    ('admin', 'view_post_revisions');
@@ -0,0 +1,9 @@
+-- Replies point to the post they answer and to the first post of the conversation.
+-- Deleting a post deletes the replies under it.
+ALTER TABLE posts
+    ADD COLUMN parent_post_id uuid REFERENCES posts (post_id) ON DELETE CASCADE DEFAULT NULL,
+    ADD COLUMN root_post_id   uuid REFERENCES posts (post_id) ON DELETE CASCADE DEFAULT NULL,
+    ADD CONSTRAINT posts_reply_has_root CHECK ((parent_post_id IS NULL) = (root_post_id IS NULL));
+
+CREATE INDEX posts_parent_post_id_idx ON posts (parent_post_id, created_at);
+CREATE INDEX posts_root_post_id_idx ON posts (root_post_id);
@@ -0,0 +1,5 @@
+-- Keyset pagination compares (created_at, post_id), which does not work with NULLs
+UPDATE posts SET created_at=CURRENT_TIMESTAMP WHERE created_at IS NULL;
+ALTER TABLE posts ALTER COLUMN created_at SET NOT NULL;
+
+CREATE INDEX posts_timeline_idx ON posts (created_at DESC, post_id DESC) WHERE parent_post_id IS NULL;
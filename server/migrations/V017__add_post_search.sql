@@ -0,0 +1,4 @@
+ALTER TABLE posts ADD COLUMN search_vector tsvector
+    GENERATED ALWAYS AS (to_tsvector('english', data)) STORED;
+
+CREATE INDEX posts_search_vector_idx ON posts USING GIN (search_vector);
@@ -0,0 +1,11 @@
+CREATE TABLE follows (
+    follower_id uuid REFERENCES users (user_id) ON DELETE CASCADE NOT NULL,
+    followee_id uuid REFERENCES users (user_id) ON DELETE CASCADE NOT NULL,
+    created_at  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (follower_id, followee_id),
+    CHECK (follower_id <> followee_id)
+);
+
+-- The primary key covers listing who a user follows
+CREATE INDEX follows_followee_id_idx ON follows (followee_id, created_at);
+CREATE INDEX follows_follower_id_created_at_idx ON follows (follower_id, created_at);
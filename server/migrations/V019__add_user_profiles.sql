@@ -0,0 +1,8 @@
+ALTER TABLE users
+    ADD COLUMN created_at   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN display_name TEXT DEFAULT NULL,
+    ADD COLUMN bio          TEXT DEFAULT NULL,
+    ADD COLUMN avatar_url   TEXT DEFAULT NULL;
+
+-- The join date of existing users is not known, the first post is the best guess
+UPDATE users u SET created_at=LEAST(u.created_at, (SELECT MIN(p.created_at) FROM posts p WHERE p.user_id=u.user_id));
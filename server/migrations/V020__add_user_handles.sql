@@ -0,0 +1,57 @@
+-- Handles are unique without regard to case or confusable characters: handle_key is the lowercase handle
+-- with 0 read as o, and 1 and i read as l. The same rules are in src/handles.rs.
+ALTER TABLE users ADD COLUMN handle TEXT;
+ALTER TABLE users
+    ADD COLUMN handle_key TEXT GENERATED ALWAYS AS (translate(lower(handle), '01i', 'oll')) STORED,
+    ADD CONSTRAINT users_handle_key_unique UNIQUE (handle_key);
+
+-- Existing users get a handle made from their username. Collisions get a number suffix,
+-- so the oldest account keeps the plain handle.
+CREATE TEMPORARY TABLE handle_backfill AS
+WITH cleaned AS (
+    SELECT user_id, created_at,
+           left(btrim(regexp_replace(regexp_replace(username, '[^A-Za-z0-9_]', '_', 'g'), '_+', '_', 'g'), '_'), 24) AS base
+    FROM users
+), allowed AS (
+    SELECT user_id, created_at,
+           CASE WHEN length(base) < 3 OR base !~ '[A-Za-z]' OR translate(lower(base), '01i', 'oll') IN (
+               SELECT translate(reserved, '01i', 'oll') FROM unnest(ARRAY[
+                   'admin', 'administrator', 'api', 'app', 'auth', 'root', 'system', 'support', 'help', 'security',
+                   'moderator', 'mod', 'staff', 'official', 'login', 'logout', 'signup', 'register', 'settings',
+                   'account', 'user', 'users', 'me', 'home', 'search', 'posts', 'null', 'undefined', 'anonymous',
+                   'everyone', 'here', 'www'
+               ]) reserved
+           ) THEN CASE WHEN base = '' THEN 'member' ELSE 'user_' || left(base, 19) END ELSE base END AS base
+    FROM cleaned
+)
+SELECT user_id, base,
+       row_number() OVER (PARTITION BY translate(lower(base), '01i', 'oll') ORDER BY created_at, user_id) AS n
+FROM allowed;
+
+UPDATE users SET handle=b.base FROM handle_backfill b WHERE b.user_id=users.user_id AND b.n = 1;
+
+-- A numbered handle can still belong to another user, for example one named bob_2,
+-- so the next free number is looked up through the handle_key index.
+DO $$
+DECLARE
+    b         RECORD;
+    candidate TEXT;
+    n         BIGINT;
+BEGIN
+    FOR b IN SELECT user_id, base, handle_backfill.n FROM handle_backfill WHERE handle_backfill.n > 1 ORDER BY base, handle_backfill.n LOOP
+        n := b.n;
+        candidate := b.base || '_' || n;
+        WHILE EXISTS(SELECT 1 FROM users WHERE handle_key = translate(lower(candidate), '01i', 'oll')) LOOP
+            n := n + 1;
+            candidate := b.base || '_' || n;
+        END LOOP;
+
+        UPDATE users SET handle=candidate WHERE user_id=b.user_id;
+    END LOOP;
+END $$;
+
+DROP TABLE handle_backfill;
+
+ALTER TABLE users
+    ALTER COLUMN handle SET NOT NULL,
+    ADD CONSTRAINT users_handle_format CHECK (handle ~ '^[A-Za-z0-9_]{3,30}$');
@@ -0,0 +1,33 @@
+CREATE TABLE post_mentions (
+    post_id uuid REFERENCES posts (post_id) ON DELETE CASCADE NOT NULL,
+    user_id uuid REFERENCES users (user_id) ON DELETE CASCADE NOT NULL,
+    PRIMARY KEY (post_id, user_id)
+);
+
+CREATE INDEX post_mentions_user_id_idx ON post_mentions (user_id);
+
+CREATE TABLE post_hashtags (
+    post_id    uuid REFERENCES posts (post_id) ON DELETE CASCADE NOT NULL,
+    -- Lowercase, without the #
+    tag        TEXT NOT NULL,
+    -- Creation time of the post, copied here for the trending query
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (post_id, tag)
+);
+
+CREATE INDEX post_hashtags_tag_idx ON post_hashtags (tag, created_at);
+CREATE INDEX post_hashtags_created_at_idx ON post_hashtags (created_at);
+
+-- Existing posts, with the same rules as src/entities.rs
+INSERT INTO post_mentions (post_id, user_id)
+SELECT DISTINCT p.post_id, u.user_id
+FROM posts p
+CROSS JOIN LATERAL regexp_matches(p.data, '(?:^|[^[:alnum:]_])@([A-Za-z0-9_]+)', 'g') m
+INNER JOIN users u ON u.handle_key = translate(lower(m[1]), '01i', 'oll')
+WHERE length(m[1]) BETWEEN 3 AND 30;
+
+INSERT INTO post_hashtags (post_id, tag, created_at)
+SELECT DISTINCT p.post_id, lower(m[1]), p.created_at
+FROM posts p
+CROSS JOIN LATERAL regexp_matches(p.data, '(?:^|[^[:alnum:]_])#([[:alnum:]_]+)', 'g') m
+WHERE length(m[1]) <= 64 AND m[1] ~ '[^[:digit:]_]';
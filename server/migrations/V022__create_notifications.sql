@@ -0,0 +1,22 @@
+CREATE TABLE notifications (
+    notification_id BIGSERIAL PRIMARY KEY,
+    -- Receiver of the notification
+    user_id         uuid REFERENCES users (user_id) ON DELETE CASCADE NOT NULL,
+    kind            TEXT NOT NULL CHECK (kind IN ('mention', 'reply', 'follow')),
+    -- User who mentioned, replied or followed
+    actor_id        uuid REFERENCES users (user_id) ON DELETE CASCADE NOT NULL,
+    post_id         uuid REFERENCES posts (post_id) ON DELETE CASCADE DEFAULT NULL,
+    created_at      TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    read_at         TIMESTAMP WITH TIME ZONE DEFAULT NULL
+);
+
+CREATE INDEX notifications_user_id_idx ON notifications (user_id, notification_id);
+CREATE INDEX notifications_unread_idx ON notifications (user_id) WHERE read_at IS NULL;
+
+-- Users without a row get all notifications
+CREATE TABLE notification_preferences (
+    user_id  uuid PRIMARY KEY REFERENCES users (user_id) ON DELETE CASCADE,
+    mentions BOOLEAN NOT NULL DEFAULT TRUE,
+    replies  BOOLEAN NOT NULL DEFAULT TRUE,
+    follows  BOOLEAN NOT NULL DEFAULT TRUE
+);
@@ -0,0 +1,11 @@
+-- Created and deleted posts, kept for a day so that stream clients can resume after reconnecting.
+-- The event id is sent to the `post_events` channel with NOTIFY when the event is committed.
+CREATE TABLE post_events (
+    event_id   BIGSERIAL PRIMARY KEY,
+    kind       TEXT NOT NULL CHECK (kind IN ('created', 'deleted')),
+    -- Not a foreign key, the post is gone after it is deleted
+    post_id    uuid NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX post_events_created_at_idx ON post_events (created_at);
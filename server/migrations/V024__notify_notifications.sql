@@ -0,0 +1,12 @@
+-- Wakes the gateway when notifications are created. They are inserted from several queries, so a trigger is used
+-- instead of calling pg_notify in each of them.
+CREATE FUNCTION notify_notification() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('notifications', NEW.notification_id::TEXT);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notifications_notify
+    AFTER INSERT ON notifications
+    FOR EACH ROW EXECUTE FUNCTION notify_notification();
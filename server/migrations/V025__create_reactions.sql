@@ -0,0 +1,11 @@
+CREATE TABLE reactions (
+    post_id    uuid REFERENCES posts (post_id) ON DELETE CASCADE NOT NULL,
+    user_id    uuid REFERENCES users (user_id) ON DELETE CASCADE NOT NULL,
+    -- One of the emoji accepted by the api
+    reaction   TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (post_id, user_id, reaction)
+);
+
+CREATE INDEX reactions_post_id_idx ON reactions (post_id, created_at, user_id);
+CREATE INDEX reactions_user_id_idx ON reactions (user_id);
@@ -0,0 +1,14 @@
+-- Posts with every column the application reads for them. Queries select from this view so that
+-- new post columns only have to be added here. Reactions are counted without marking the viewer's own,
+-- which are read separately for each page.
+CREATE VIEW post_view AS
+SELECT p.post_id, p.user_id, p.created_at, p.edited_at, p.data AS text, p.parent_post_id, p.root_post_id,
+       u.username, u.handle,
+       (SELECT COUNT(*) FROM posts r WHERE r.parent_post_id=p.post_id) AS reply_count,
+       (SELECT COALESCE(jsonb_object_agg(mu.handle_key, mu.user_id), '{}') FROM post_mentions m
+        INNER JOIN users mu ON mu.user_id = m.user_id WHERE m.post_id=p.post_id) AS mentions,
+       (SELECT COALESCE(jsonb_agg(jsonb_build_object('reaction', rc.reaction, 'count', rc.count)
+                                  ORDER BY rc.count DESC, rc.reaction), '[]')
+        FROM (SELECT reaction, COUNT(*) AS count FROM reactions WHERE post_id=p.post_id GROUP BY reaction) rc) AS reactions
+FROM posts p
+INNER JOIN users u ON u.user_id = p.user_id;
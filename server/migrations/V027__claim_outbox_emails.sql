@@ -0,0 +1,3 @@
+-- Set when a server instance takes the email for sending, so that the rows do not stay locked while it is sent.
+-- Claims older than the claim timeout are taken over, in case the instance stopped before finishing.
+ALTER TABLE email_outbox ADD COLUMN claimed_at TIMESTAMP WITH TIME ZONE DEFAULT NULL;
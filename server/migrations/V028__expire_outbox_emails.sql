@@ -0,0 +1,5 @@
+-- Emails can contain links with secret tokens. The body is cleared once the email has been sent,
+-- and unsent emails are deleted once their link has expired, so the tokens cannot be read from the table.
+ALTER TABLE email_outbox ADD COLUMN expires_at TIMESTAMP WITH TIME ZONE DEFAULT NULL;
+
+UPDATE email_outbox SET body='' WHERE sent_at IS NOT NULL;
@@ -0,0 +1,24 @@
+-- Sequence values are handed out when a row is inserted, not when it is committed. Readers go through the events
+-- by id, so an event committed after a higher id had already been read would never be sent. The id is therefore
+-- taken under a lock that is held until the inserting transaction ends, which makes ids commit in order.
+-- The lock is taken last in the transactions that create or delete posts, so waiting for it is short.
+CREATE FUNCTION assign_post_event_id() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext('post_events'));
+    NEW.event_id := nextval('post_events_event_id_seq');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE post_events ALTER COLUMN event_id DROP DEFAULT;
+
+CREATE TRIGGER post_events_assign_id
+    BEFORE INSERT ON post_events
+    FOR EACH ROW EXECUTE FUNCTION assign_post_event_id();
+
+-- Newest event deleted after the retention period. Clients resuming from an older event have missed some.
+CREATE TABLE post_events_pruned (
+    up_to BIGINT NOT NULL
+);
+
+INSERT INTO post_events_pruned (up_to) VALUES (0);
@@ -0,0 +1,15 @@
+-- Notification ids commit in order for the same reason as post event ids (see V029): the gateway and resuming
+-- sockets read the notifications by id and would skip one committed after a higher id had been read.
+CREATE FUNCTION assign_notification_id() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext('notifications'));
+    NEW.notification_id := nextval('notifications_notification_id_seq');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE notifications ALTER COLUMN notification_id DROP DEFAULT;
+
+CREATE TRIGGER notifications_assign_id
+    BEFORE INSERT ON notifications
+    FOR EACH ROW EXECUTE FUNCTION assign_notification_id();
@@ -0,0 +1,216 @@
+use actix_web::{delete, error, get, HttpRequest, HttpResponse, post, put, Result, web};
+use actix_web_validator::{Json, Query};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::api::errors::ErrorResponse;
+use crate::api::utilities::{Identity, require_permission};
+use crate::audit::{self, EventType};
+use crate::db;
+use crate::db::audit::AuditFilter;
+use crate::db::models::{AuditEvent, Permission, Role};
+use crate::models::AppState;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(clear_lockout)
+        .service(get_roles)
+        .service(set_user_role)
+        .service(suspend_user)
+        .service(ban_user)
+        .service(lift_restriction)
+        .service(get_audit_events);
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ClearLockoutData {
+    #[validate(length(min = 1, max = 320))]
+    email: Option<String>,
+    #[validate(length(min = 1, max = 64))]
+    ip: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ClearLockoutResponse {
+    cleared: u64
+}
+
+#[post("/lockouts/clear")]
+pub async fn clear_lockout(req: HttpRequest, identity: Identity, data: web::Data<AppState>, body: Json<ClearLockoutData>) -> Result<HttpResponse, error::Error> {
+    let client = data.get_client().await?;
+    let user_id = require_permission(&identity, &client, Permission::ClearLockouts).await?;
+
+    if body.email.is_none() && body.ip.is_none() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Either email or ip must be given" }))
+    }
+
+    let mut cleared = 0;
+    if let Some(email) = &body.email {
+        cleared += db::login_attempts::clear(&client, db::login_attempts::ACCOUNT, &email.to_lowercase()).await?;
+    }
+    if let Some(ip) = &body.ip {
+        cleared += db::login_attempts::clear(&client, db::login_attempts::IP, ip).await?;
+    }
+
+    audit::record(&client, &req, EventType::LockoutCleared, Some(&user_id), None, json!({ "email": body.email, "ip": body.ip })).await?;
+
+    Ok(HttpResponse::Ok().json(ClearLockoutResponse { cleared }))
+}
+
+#[derive(Serialize)]
+struct RolesList {
+    roles: Vec<Role>
+}
+
+#[get("/roles")]
+pub async fn get_roles(identity: Identity, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let client = data.get_client().await?;
+    require_permission(&identity, &client, Permission::ManageRoles).await?;
+
+    let roles = db::roles::get_roles(&client).await?;
+
+    Ok(HttpResponse::Ok().json(RolesList { roles }))
+}
+
+#[derive(Deserialize)]
+pub struct UserPath {
+    user_id: Uuid,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct SetRoleData {
+    #[validate(length(min = 1, max = 64))]
+    role: String,
+}
+
+#[put("/users/{user_id}/role")]
+pub async fn set_user_role(req: HttpRequest, identity: Identity, path: web::Path<UserPath>, data: web::Data<AppState>, body: Json<SetRoleData>) -> Result<HttpResponse, error::Error> {
+    let client = data.get_client().await?;
+    let user_id = require_permission(&identity, &client, Permission::ManageRoles).await?;
+
+    // Prevents administrators from accidentally removing their own access
+    if user_id == path.user_id {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Cannot change your own role" }))
+    }
+
+    if !db::roles::set_user_role(&client, &path.user_id, &body.role).await? {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "User or role not found" }))
+    }
+
+    audit::record(&client, &req, EventType::RoleChanged, Some(&user_id), Some(&path.user_id), json!({ "role": body.role })).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize, Validate)]
+pub struct SuspendData {
+    #[validate(length(min = 1, max = 1000))]
+    reason: String,
+    // At most a year. Longer restrictions should be bans.
+    #[validate(range(min = 1, max = 8760))]
+    hours: i32,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct BanData {
+    #[validate(length(min = 1, max = 1000))]
+    reason: String,
+}
+
+/// Suspends the user for the given time or bans them permanently if no time is given.
+async fn restrict_user(req: HttpRequest, identity: Identity, data: &AppState, target_id: &Uuid, reason: &String, hours: Option<i32>) -> Result<HttpResponse, error::Error> {
+    let mut client = data.get_client().await?;
+    let user_id = require_permission(&identity, &client, Permission::RestrictUsers).await?;
+
+    // Users who can restrict others cannot be restricted, so they cannot lock each other out
+    if user_id == *target_id || db::roles::has_permission(&client, target_id, Permission::RestrictUsers).await? {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "This user cannot be suspended or banned" }))
+    }
+
+    if !db::restrictions::restrict_user(&mut client, target_id, &user_id, reason, hours).await? {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "User not found" }))
+    }
+
+    let event_type = if hours.is_some() { EventType::UserSuspended } else { EventType::UserBanned };
+    audit::record(&client, &req, event_type, Some(&user_id), Some(target_id), json!({ "reason": reason, "hours": hours })).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[post("/users/{user_id}/suspend")]
+pub async fn suspend_user(req: HttpRequest, identity: Identity, path: web::Path<UserPath>, data: web::Data<AppState>, body: Json<SuspendData>) -> Result<HttpResponse, error::Error> {
+    restrict_user(req, identity, &data, &path.user_id, &body.reason, Some(body.hours)).await
+}
+
+#[post("/users/{user_id}/ban")]
+pub async fn ban_user(req: HttpRequest, identity: Identity, path: web::Path<UserPath>, data: web::Data<AppState>, body: Json<BanData>) -> Result<HttpResponse, error::Error> {
+    restrict_user(req, identity, &data, &path.user_id, &body.reason, None).await
+}
+
+#[delete("/users/{user_id}/restriction")]
+pub async fn lift_restriction(req: HttpRequest, identity: Identity, path: web::Path<UserPath>, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let client = data.get_client().await?;
+    let user_id = require_permission(&identity, &client, Permission::RestrictUsers).await?;
+
+    if !db::restrictions::lift_restriction(&client, &path.user_id).await? {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "User not found" }))
+    }
+
+    audit::record(&client, &req, EventType::RestrictionLifted, Some(&user_id), Some(&path.user_id), json!({})).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditParams {
+    #[validate(length(min = 1, max = 64))]
+    event_type: Option<String>,
+    actor_id: Option<Uuid>,
+    target_id: Option<Uuid>,
+    #[validate(length(min = 1, max = 64))]
+    ip: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    // Id of the last event of the previous page
+    before: Option<i64>,
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditEventsList {
+    events: Vec<AuditEvent>,
+    // Passed as `before` to get the next page. None if there are no more events.
+    next_cursor: Option<i64>,
+}
+
+#[get("/audit")]
+pub async fn get_audit_events(identity: Identity, data: web::Data<AppState>, query: Query<AuditParams>) -> Result<HttpResponse, error::Error> {
+    let client = data.get_client().await?;
+    require_permission(&identity, &client, Permission::ViewAuditLog).await?;
+
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(50);
+    let filter = AuditFilter {
+        event_type: query.event_type,
+        actor_id: query.actor_id,
+        target_id: query.target_id,
+        ip: query.ip,
+        since: query.since,
+        until: query.until,
+    };
+
+    let events = db::audit::get_events(&client, &filter, query.before, limit).await?;
+    let next_cursor = if events.len() as i64 == limit {
+        events.last().map(|event| event.event_id)
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(AuditEventsList { events, next_cursor }))
+}
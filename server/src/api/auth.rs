@@ -1,19 +1,37 @@
 use actix_session::Session;
-use actix_web::{HttpResponse, post, Result, web};
+use actix_web::{HttpRequest, HttpResponse, post, Result, web};
 use actix_web::http::{header, StatusCode};
 use header::LOCATION;
+use actix_web_validator::Json;
+use log::debug;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::Validate;
 
-use crate::api::errors::ErrorResponse;
-use crate::api::utilities::get_session_user;
+use crate::api::errors::{ApiError, ErrorResponse};
+use crate::audit::{self, EventType};
+use crate::api::two_factor::normalize_recovery_code;
+use crate::api::user::send_verification_email;
+use crate::api::utilities::{clear_login_failures, clear_pending_login, get_pending_user, get_session_user, lockout_response, login_user, password_reset_throttle_response, record_login_failure, restricted_response, start_pending_login, verify_password};
 use crate::db;
+use crate::db::models::TwoFactorMethod;
+use crate::mailer::Email;
 use crate::middleware::Csrf;
 use crate::models::AppState;
+use crate::tokens::{generate_token, hash_token};
+use crate::totp;
+
+const RESET_TOKEN_LENGTH: usize = 48;
+const RESET_TOKEN_VALID_SECONDS: i64 = 30 * 60;
+
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg
         .service(login)
+        .service(login_two_factor)
         .service(logout)
+        .service(forgot_password)
+        .service(reset_password)
         .service(get_csrf);
 }
 
@@ -24,33 +42,158 @@ struct LoginForm {
 }
 
 #[post("/login")]
-async fn login(session: Session, data: web::Data<AppState>, form: web::Json<LoginForm>) -> Result<HttpResponse> {
+async fn login(req: HttpRequest, session: Session, data: web::Data<AppState>, form: web::Json<LoginForm>) -> Result<HttpResponse> {
     if get_session_user(&session)?.is_some() {
         return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Already logged in" }));
     }
 
-    let user = db::user::authenticate(&data.get_client().await?, &form.email, &form.password).await?;
+    let client = data.get_client().await?;
+
+    if let Some(response) = lockout_response(&req, &client, &form.email).await? {
+        audit::record(&client, &req, EventType::LoginFailed, None, None, json!({ "email": form.email, "reason": "locked_out" })).await?;
+        return Ok(response)
+    }
+
+    // Every failure does a password check and records the attempt, so the response does not reveal if the account exists
+    let found = db::user::get_user_by_email(&client, &form.email).await?;
+    let target_id = found.as_ref().map(|(user, _)| user.user_id);
+    let mut failure_reason = "invalid_credentials";
+
+    let user = match found {
+        Some((user, pwhash)) => verify_password(&data, &client, &user.user_id, pwhash, &form.password).await?
+            .map(|_| user),
+        None => {
+            data.password_hasher.verify_dummy(&form.password).await.map_err(ApiError::from)?;
+            None
+        }
+    };
+
+    let user = match user {
+        Some(user) if user.email_verified || !data.require_verified_email => Some(user),
+        Some(user) => {
+            // Signing up does not reveal if the email is taken, so an unverified account fails like a wrong password.
+            // The owner of the email gets a new link to finish the sign up, unless too many have been sent already.
+            send_verification_email(&data, &client, user.user_id, &user.email).await?;
+            failure_reason = "email_not_verified";
+            None
+        },
+        None => None
+    };
 
     let user = match user {
         Some(user) => user,
-        None => return Ok(HttpResponse::build(StatusCode::UNAUTHORIZED).body("Forbidden"))
+        None => {
+            record_login_failure(&req, &data, &client, &form.email).await?;
+            audit::record(&client, &req, EventType::LoginFailed, None, target_id.as_ref(), json!({ "email": form.email, "reason": failure_reason })).await?;
+            return Ok(HttpResponse::build(StatusCode::UNAUTHORIZED).body("Forbidden"))
+        }
     };
 
-    session.renew();
-    session.remove("csrf");
-    session.insert("user_id", user.user_id.clone())?;
+    if let Some(response) = restricted_response(&client, &user.user_id).await? {
+        audit::record(&client, &req, EventType::LoginFailed, None, Some(&user.user_id), json!({ "email": form.email, "reason": "restricted" })).await?;
+        return Ok(response)
+    }
+
+    if user.two_factor_enabled {
+        // The user is only logged in after the second factor has been verified, and the failures of the account
+        // are kept until then so that knowing the password does not give more guesses at the second factor
+        start_pending_login(&session, user.user_id)?;
+
+        return Ok(HttpResponse::Ok().json(TwoFactorRequiredResponse { two_factor_required: true, methods: user.two_factor_methods }))
+    }
+
+    clear_login_failures(&client, &user.email).await?;
+    login_user(&req, &session, user.user_id)?;
+    audit::record(&client, &req, EventType::LoginSucceeded, Some(&user.user_id), Some(&user.user_id), json!({ "method": "password" })).await?;
 
     Ok(HttpResponse::Ok()
         .json(user))
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorRequiredResponse {
+    two_factor_required: bool,
+    // `totp` is finished with `/api/auth/login/2fa` and `passkey` with `/api/auth/webauthn/2fa/*`
+    methods: Vec<TwoFactorMethod>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorForm {
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
+#[post("/login/2fa")]
+async fn login_two_factor(req: HttpRequest, session: Session, data: web::Data<AppState>, form: web::Json<TwoFactorForm>) -> Result<HttpResponse> {
+    let user_id = match get_pending_user(&session)? {
+        Some(user_id) => user_id,
+        None => return Ok(HttpResponse::Unauthorized().json(ErrorResponse { error: "No login in progress" }))
+    };
+
+    let client = data.get_client().await?;
+    let user = match db::user::get_user(&client, &user_id).await? {
+        Some(user) => user,
+        None => return Err(ApiError::Unauthorized.into())
+    };
+    let method = if form.code.is_some() { "totp" } else { "recovery_code" };
+
+    if let Some(response) = lockout_response(&req, &client, &user.email).await? {
+        clear_pending_login(&session);
+        audit::record(&client, &req, EventType::LoginFailed, None, Some(&user_id), json!({ "method": method, "reason": "locked_out" })).await?;
+        return Ok(response)
+    }
+
+    let verified = match (&form.code, &form.recovery_code) {
+        (Some(code), None) => {
+            let secret = db::totp::get_totp_state(&client, &user_id).await?
+                .filter(|state| state.enabled)
+                .and_then(|state| state.secret);
+
+            match secret.and_then(|secret| totp::verify_code(&secret, code)) {
+                Some(step) => db::totp::use_totp_step(&client, &user_id, step).await?,
+                None => false
+            }
+        },
+        (None, Some(recovery_code)) => {
+            let code_hash = hash_token(&normalize_recovery_code(recovery_code));
+            db::totp::use_recovery_code(&client, &user_id, &code_hash).await?
+        },
+        _ => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Either code or recoveryCode must be given" }))
+    };
+
+    if !verified {
+        record_login_failure(&req, &data, &client, &user.email).await?;
+        audit::record(&client, &req, EventType::LoginFailed, None, Some(&user_id), json!({ "method": method, "reason": "invalid_code" })).await?;
+        return Ok(HttpResponse::Unauthorized().json(ErrorResponse { error: "Invalid code" }))
+    }
+
+    if let Some(response) = restricted_response(&client, &user_id).await? {
+        clear_pending_login(&session);
+        audit::record(&client, &req, EventType::LoginFailed, None, Some(&user_id), json!({ "method": method, "reason": "restricted" })).await?;
+        return Ok(response)
+    }
+
+    clear_pending_login(&session);
+    clear_login_failures(&client, &user.email).await?;
+    login_user(&req, &session, user.user_id)?;
+    audit::record(&client, &req, EventType::LoginSucceeded, Some(&user.user_id), Some(&user.user_id), json!({ "method": method })).await?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
 #[post("/logout")]
-async fn logout(session: Session) -> HttpResponse {
+async fn logout(req: HttpRequest, session: Session, data: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Some(user_id) = get_session_user(&session)? {
+        audit::record(&data.get_client().await?, &req, EventType::Logout, Some(&user_id), Some(&user_id), json!({})).await?;
+    }
+
     session.purge();
 
-    HttpResponse::SeeOther()
+    Ok(HttpResponse::SeeOther()
         .insert_header((LOCATION, "/"))
-        .finish()
+        .finish())
 }
 
 #[derive(Serialize)]
@@ -64,3 +207,73 @@ async fn get_csrf(session: Session, csrf: Csrf) -> Result<HttpResponse> {
 
     Ok(HttpResponse::Ok().json(CsrfResponse { csrf: token }))
 }
+
+#[derive(Deserialize, Validate)]
+pub struct ForgotPasswordData {
+    #[validate(email)]
+    email: String,
+}
+
+#[derive(Serialize)]
+pub struct MessageResponse {
+    pub message: &'static str
+}
+
+#[post("/forgot")]
+async fn forgot_password(req: HttpRequest, data: web::Data<AppState>, body: Json<ForgotPasswordData>) -> Result<HttpResponse> {
+    let client = data.get_client().await?;
+
+    if let Some(response) = password_reset_throttle_response(&req, &data, &client, &body.email).await? {
+        return Ok(response)
+    }
+
+    // The token and email are always generated so that the response time does not reveal if the account exists
+    let token = generate_token(RESET_TOKEN_LENGTH);
+    let mut link = data.app_url.join("reset-password")
+        .map_err(|err| {
+            debug!("Failed to create password reset link. {}", err);
+            ApiError::InternalServerError
+        })?;
+    link.query_pairs_mut().append_pair("token", &token);
+
+    let email = Email {
+        recipient: body.email.clone(),
+        subject: "Password reset".to_string(),
+        body: format!(
+            "A password reset was requested for your account. Use the link below to choose a new password.\n\n{}\n\nThe link expires in {} minutes. If you did not request a reset you can ignore this email.",
+            link,
+            RESET_TOKEN_VALID_SECONDS / 60
+        ),
+    };
+
+    db::password_reset::create_reset_token(&client, &email, &hash_token(&token), RESET_TOKEN_VALID_SECONDS).await?;
+
+    Ok(HttpResponse::Ok().json(MessageResponse { message: "If an account with the email exists, a password reset link has been sent to it" }))
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ResetPasswordData {
+    #[validate(length(min = 1, max = 128))]
+    token: String,
+    #[validate(length(min = 8, max = 128))]
+    new_password: String,
+}
+
+#[post("/reset")]
+async fn reset_password(req: HttpRequest, session: Session, data: web::Data<AppState>, body: Json<ResetPasswordData>) -> Result<HttpResponse> {
+    let new_pwhash = data.password_hasher.hash(&body.new_password).await.map_err(ApiError::from)?;
+    let mut client = data.get_client().await?;
+
+    let user_id = match db::password_reset::reset_password(&mut client, &hash_token(&body.token), &new_pwhash).await? {
+        Some(user_id) => user_id,
+        None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid or expired token" }))
+    };
+
+    audit::record(&client, &req, EventType::PasswordReset, None, Some(&user_id), json!({})).await?;
+
+    // The user has to log in again with the new password
+    session.purge();
+
+    Ok(HttpResponse::Ok().json(MessageResponse { message: "Password changed" }))
+}
@@ -1,10 +1,12 @@
 use actix_web::{error, HttpResponse};
 use actix_web::body::BoxBody;
 use actix_web::http::StatusCode;
-use actix_web_validator::JsonConfig;
+use actix_web_validator::{JsonConfig, QueryConfig};
 use derive_more::{Display, Error};
 use serde::Serialize;
 
+use crate::password::PasswordError;
+
 #[derive(Serialize)]
 pub struct ErrorResponse<T> where T: Serialize {
     pub error: T
@@ -34,7 +36,7 @@ impl error::ResponseError for ApiError {
             ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
             ApiError::Forbidden => StatusCode::FORBIDDEN,
             ApiError::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
-            ApiError::WithMessage { status_code, .. } => status_code.clone()
+            ApiError::WithMessage { status_code, .. } => status_code
         }
     }
 
@@ -49,6 +51,12 @@ impl error::ResponseError for ApiError {
     }
 }
 
+impl From<PasswordError> for ApiError {
+    fn from(_: PasswordError) -> Self {
+        ApiError::InternalServerError
+    }
+}
+
 #[derive(Serialize)]
 struct FieldError {
     field: String,
@@ -97,3 +105,23 @@ pub fn generate_json_config() -> JsonConfig {
             }
         })
 }
+
+pub fn generate_query_config() -> QueryConfig {
+    QueryConfig::default()
+        .error_handler(|err, _req| {
+            match err {
+                // Validation error
+                actix_web_validator::Error::Validate(err) => error::InternalError::from_response(err.clone(),
+                    HttpResponse::BadRequest().json(
+                        ErrorResponse { error: ValidationErrorJsonPayload::from(&err) })
+                ).into(),
+
+                // Missing parameters or invalid data types
+                actix_web_validator::Error::Deserialize(err) => {
+                    let resp = ErrorResponse { error: err.to_string() };
+                    error::InternalError::from_response(err, HttpResponse::BadRequest().json(resp)).into()
+                },
+                _ => error::InternalError::from_response(err, HttpResponse::BadRequest().finish()).into()
+            }
+        })
+}
@@ -0,0 +1,125 @@
+use actix_session::Session;
+use actix_web::{delete, error, get, HttpResponse, put, Result, web};
+use actix_web_validator::Query;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::api::errors::ErrorResponse;
+use crate::api::utilities::require_user;
+use crate::db;
+use crate::db::models::{Cursor, FollowUser};
+use crate::models::AppState;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(get_counts)
+        .service(get_followers)
+        .service(get_following)
+        .service(follow)
+        .service(unfollow);
+}
+
+#[derive(Deserialize)]
+pub struct UserPath {
+    user_id: Uuid,
+}
+
+#[derive(Serialize)]
+struct FollowResponse {
+    message: &'static str
+}
+
+#[put("/{user_id}")]
+pub async fn follow(session: Session, path: web::Path<UserPath>, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let client = data.get_client().await?;
+
+    if user_id == path.user_id {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "You cannot follow yourself" }))
+    }
+
+    if db::user::get_user(&client, &path.user_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "User not found" }))
+    }
+
+    db::follows::follow(&client, &user_id, &path.user_id).await?;
+
+    Ok(HttpResponse::Ok().json(FollowResponse { message: "User followed" }))
+}
+
+#[delete("/{user_id}")]
+pub async fn unfollow(session: Session, path: web::Path<UserPath>, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+
+    db::follows::unfollow(&data.get_client().await?, &user_id, &path.user_id).await?;
+
+    Ok(HttpResponse::Ok().json(FollowResponse { message: "User unfollowed" }))
+}
+
+#[get("/{user_id}")]
+pub async fn get_counts(path: web::Path<UserPath>, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let client = data.get_client().await?;
+
+    if db::user::get_user(&client, &path.user_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "User not found" }))
+    }
+
+    Ok(HttpResponse::Ok().json(db::follows::get_counts(&client, &path.user_id).await?))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct FollowListParams {
+    // Cursor from an earlier response
+    before: Option<String>,
+    #[validate(range(min = 1, max = 50))]
+    limit: Option<i32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FollowList {
+    users: Vec<FollowUser>,
+    // Passed as `before` to get the next page. None if there are no more users.
+    next_cursor: Option<String>,
+}
+
+#[get("/{user_id}/followers")]
+pub async fn get_followers(path: web::Path<UserPath>, data: web::Data<AppState>, query: Query<FollowListParams>) -> Result<HttpResponse, error::Error> {
+    list_follows(path.user_id, data, query, true).await
+}
+
+#[get("/{user_id}/following")]
+pub async fn get_following(path: web::Path<UserPath>, data: web::Data<AppState>, query: Query<FollowListParams>) -> Result<HttpResponse, error::Error> {
+    list_follows(path.user_id, data, query, false).await
+}
+
+async fn list_follows(user_id: Uuid, data: web::Data<AppState>, query: Query<FollowListParams>, followers: bool) -> Result<HttpResponse, error::Error> {
+    let client = data.get_client().await?;
+    let limit = query.limit.unwrap_or(20);
+
+    let before = match &query.before {
+        Some(cursor) => match Cursor::decode(cursor) {
+            Some(cursor) => Some(cursor),
+            None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid cursor" }))
+        },
+        None => None
+    };
+
+    if db::user::get_user(&client, &user_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "User not found" }))
+    }
+
+    // One extra user is fetched to know if there is another page
+    let mut users = if followers {
+        db::follows::get_followers(&client, &user_id, before.as_ref(), limit + 1).await?
+    } else {
+        db::follows::get_following(&client, &user_id, before.as_ref(), limit + 1).await?
+    };
+    let has_more = users.len() > limit as usize;
+    users.truncate(limit as usize);
+
+    let next_cursor = users.last().filter(|_| has_more).map(|user| Cursor::from(user).encode());
+
+    Ok(HttpResponse::Ok().json(FollowList { users, next_cursor }))
+}
@@ -0,0 +1,48 @@
+use actix_session::Session;
+use actix_web::{Error, get, HttpRequest, HttpResponse, web};
+use actix_web_validator::Query;
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::api::errors::{ApiError, ErrorResponse};
+use crate::api::utilities::{get_session_public_id, require_user};
+use crate::db;
+use crate::db::models::PostUser;
+use crate::gateway::MAX_SOCKETS_PER_USER;
+use crate::middleware::verify_origin;
+use crate::models::AppState;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(connect);
+}
+
+#[derive(Deserialize, Validate)]
+pub struct GatewayParams {
+    // Token from the `ready` message of the previous socket
+    #[validate(length(max = 64))]
+    resume: Option<String>,
+}
+
+/// Opens a WebSocket for notifications, typing indicators and presence.
+/// Only the session cookie is accepted, and the handshake must come from the frontend.
+#[get("")]
+pub async fn connect(req: HttpRequest, body: web::Payload, session: Session, data: web::Data<AppState>, query: Query<GatewayParams>) -> Result<HttpResponse, Error> {
+    let user_id = require_user(&session)?;
+    verify_origin(&req, &session).await?;
+    let public_id = get_session_public_id(&session)?.ok_or(ApiError::Forbidden)?;
+
+    if data.gateway.socket_count(&user_id) >= MAX_SOCKETS_PER_USER {
+        return Ok(HttpResponse::TooManyRequests().json(ErrorResponse { error: "Too many open connections" }))
+    }
+
+    let user = match db::profiles::get_profile(&data.get_client().await?, &user_id).await? {
+        Some(profile) => PostUser { user_id, username: profile.username, handle: profile.handle },
+        None => return Err(ApiError::Forbidden.into())
+    };
+
+    let (response, ws_session, stream) = actix_ws::handle(&req, body)?;
+    let connection = data.gateway.connect(&user, &public_id, query.resume.as_deref()).await?;
+    actix_web::rt::spawn(data.gateway.clone().run_socket(ws_session, stream, user, public_id, connection));
+
+    Ok(response)
+}
@@ -1,5 +1,14 @@
+pub mod admin;
 pub mod user;
 pub mod errors;
+pub mod follows;
+pub mod gateway;
 pub mod auth;
+pub mod notifications;
 pub mod posts;
+pub mod profiles;
+pub mod reactions;
+pub mod tokens;
+pub mod two_factor;
+pub mod webauthn;
 pub mod utilities;
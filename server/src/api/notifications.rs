@@ -0,0 +1,113 @@
+use actix_session::Session;
+use actix_web::{error, get, HttpResponse, post, put, Result, web};
+use actix_web_validator::{Json, Query};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::api::errors::ErrorResponse;
+use crate::api::utilities::require_user;
+use crate::db;
+use crate::db::models::{Notification, NotificationPreferences};
+use crate::models::AppState;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(get_notifications)
+        .service(get_unread_count)
+        .service(mark_all_read)
+        .service(mark_read)
+        .service(get_preferences)
+        .service(set_preferences);
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationParams {
+    #[serde(default)]
+    unread_only: bool,
+    // Id of the last notification of the previous page
+    before: Option<i64>,
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NotificationsList {
+    notifications: Vec<Notification>,
+    unread_count: i64,
+    // Passed as `before` to get the next page. None if there are no more notifications.
+    next_cursor: Option<i64>,
+}
+
+#[get("")]
+pub async fn get_notifications(session: Session, data: web::Data<AppState>, query: Query<NotificationParams>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let client = data.get_client().await?;
+    let limit = query.limit.unwrap_or(20);
+
+    let notifications = db::notifications::get_notifications(&client, &user_id, query.unread_only, query.before, limit).await?;
+    let unread_count = db::notifications::count_unread(&client, &user_id).await?;
+    let next_cursor = if notifications.len() as i64 == limit {
+        notifications.last().map(|notification| notification.notification_id)
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(NotificationsList { notifications, unread_count, next_cursor }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UnreadCount {
+    unread_count: i64,
+}
+
+/// Cheaper than listing the notifications, for polling
+#[get("/unread")]
+pub async fn get_unread_count(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let unread_count = db::notifications::count_unread(&data.get_client().await?, &user_id).await?;
+
+    Ok(HttpResponse::Ok().json(UnreadCount { unread_count }))
+}
+
+#[derive(Deserialize)]
+pub struct NotificationPath {
+    notification_id: i64,
+}
+
+#[post("/{notification_id}/read")]
+pub async fn mark_read(session: Session, path: web::Path<NotificationPath>, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+
+    if !db::notifications::mark_read(&data.get_client().await?, &user_id, path.notification_id).await? {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Notification not found" }))
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[post("/read-all")]
+pub async fn mark_all_read(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    db::notifications::mark_all_read(&data.get_client().await?, &user_id).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/preferences")]
+pub async fn get_preferences(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let preferences = db::notifications::get_preferences(&data.get_client().await?, &user_id).await?;
+
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+#[put("/preferences")]
+pub async fn set_preferences(session: Session, data: web::Data<AppState>, body: Json<NotificationPreferences>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    db::notifications::set_preferences(&data.get_client().await?, &user_id, &body).await?;
+
+    Ok(HttpResponse::Ok().json(body.into_inner()))
+}
@@ -1,20 +1,34 @@
-use actix_session::Session;
-use actix_web::{delete, Error, get, HttpResponse, post, Result, web};
+use actix_web::{delete, Error, get, HttpRequest, HttpResponse, patch, post, Result, web};
+use actix_web::http::{header, StatusCode};
 use actix_web_validator::{Json, Query};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Client;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use uuid::Uuid;
 use validator::Validate;
 
-use crate::api::errors::ErrorResponse;
-use crate::api::utilities::require_user;
+use crate::api::errors::{ApiError, ErrorResponse};
+use crate::api::utilities::{Identity, require_permission, require_scope, viewer_id};
+use crate::audit::{self, EventType};
 use crate::db;
-use crate::db::models::Post;
+use crate::db::models::{Cursor, Permission, Post, PostRevision, Scope, SearchCursor, SearchResult, TrendingTag};
+use crate::db::posts::{PostFilter, PostSearch};
+use crate::entities;
 use crate::models::AppState;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg
         .service(get_posts)
+        .service(get_home)
+        .service(get_tag)
+        .service(get_trending_tags)
+        .service(search_posts)
+        .service(stream_posts)
         .service(create_post)
+        .service(get_revisions)
+        .service(get_thread)
+        .service(edit_post)
         .service(delete_post_admin)
         .service(delete_post);
 }
@@ -23,26 +37,223 @@ pub fn config(cfg: &mut web::ServiceConfig) {
 pub struct ListParams {
     #[validate(range(min = 0, max = 50))]
     limit: Option<i32>,
+    // Kept for older clients, cursors should be used instead
     #[validate(range(min = 0, max = 1000))]
     offset: Option<i32>,
+    // Cursors from an earlier response. `before` gets older posts and `after` newer posts.
+    before: Option<String>,
+    after: Option<String>,
 }
 
 #[derive(Serialize)]
-struct PostsList {
-    posts: Vec<Post>
+#[serde(rename_all = "camelCase")]
+pub struct PostsList {
+    posts: Vec<Post>,
+    // Passed as `before` to get older posts. None if there are no more posts.
+    next_cursor: Option<String>,
+    // Passed as `after` to get newer posts. None if this is the newest page.
+    prev_cursor: Option<String>,
 }
 
 #[get("")]
-pub async fn get_posts(data: web::Data<AppState>, query: Query<ListParams>) -> Result<HttpResponse> {
-    let posts = db::posts::get_posts(&data.get_client().await?, query.limit.unwrap_or(10), query.offset.unwrap_or(0)).await?;
+pub async fn get_posts(identity: Identity, data: web::Data<AppState>, query: Query<ListParams>) -> Result<HttpResponse> {
+    let viewer_id = viewer_id(&identity)?;
+    let posts = list_posts(&data.get_client().await?, &query, &PostFilter::default(), viewer_id.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(posts))
+}
+
+/// Posts by the user and the users they follow
+#[get("/home")]
+pub async fn get_home(identity: Identity, data: web::Data<AppState>, query: Query<ListParams>) -> Result<HttpResponse, Error> {
+    let user_id = require_scope(&identity, Scope::ReadPosts)?;
+    let filter = PostFilter { home_of: Some(user_id), ..Default::default() };
+    let posts = list_posts(&data.get_client().await?, &query, &filter, Some(&user_id)).await?;
+
+    Ok(HttpResponse::Ok().json(posts))
+}
+
+#[derive(Deserialize)]
+pub struct TagPath {
+    tag: String,
+}
+
+/// Posts and replies with the hashtag
+#[get("/tag/{tag}")]
+pub async fn get_tag(identity: Identity, path: web::Path<TagPath>, data: web::Data<AppState>, query: Query<ListParams>) -> Result<HttpResponse, Error> {
+    let viewer_id = viewer_id(&identity)?;
+    let filter = PostFilter { tag: Some(entities::normalize_tag(&path.tag)), ..Default::default() };
+    let posts = list_posts(&data.get_client().await?, &query, &filter, viewer_id.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(posts))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct TrendingParams {
+    // Length of the time window
+    #[validate(range(min = 1, max = 168))]
+    hours: Option<i32>,
+    #[validate(range(min = 1, max = 50))]
+    limit: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct TrendingTags {
+    tags: Vec<TrendingTag>
+}
+
+#[get("/trending")]
+pub async fn get_trending_tags(data: web::Data<AppState>, query: Query<TrendingParams>) -> Result<HttpResponse> {
+    let tags = db::posts::get_trending_tags(&data.get_client().await?, query.hours.unwrap_or(24), query.limit.unwrap_or(10)).await?;
+
+    Ok(HttpResponse::Ok().json(TrendingTags { tags }))
+}
+
+/// Gets a page of posts with either the cursors or the offset in the query.
+/// `viewer_id` is the user whose reactions are marked in the posts.
+pub async fn list_posts(client: &Client, query: &ListParams, filter: &PostFilter, viewer_id: Option<&Uuid>) -> Result<PostsList, Error> {
+    let limit = query.limit.unwrap_or(10);
+
+    if query.offset.is_some() && (query.before.is_some() || query.after.is_some()) {
+        return Err(bad_request("Offset cannot be used with cursors"))
+    }
+
+    // One extra post is fetched to know if there is another page
+    let (posts, has_older, has_newer) = match (&query.before, &query.after) {
+        (Some(_), Some(_)) => return Err(bad_request("Only one of before and after can be used")),
+        (Some(cursor), None) => {
+            let cursor = Cursor::decode(cursor).ok_or_else(|| bad_request("Invalid cursor"))?;
+            let mut posts = db::posts::get_posts_before(client, filter, viewer_id, &cursor, limit + 1).await?;
+            let has_older = posts.len() > limit as usize;
+            posts.truncate(limit as usize);
+            (posts, has_older, true)
+        }
+        (None, Some(cursor)) => {
+            let cursor = Cursor::decode(cursor).ok_or_else(|| bad_request("Invalid cursor"))?;
+            let mut posts = db::posts::get_posts_after(client, filter, viewer_id, &cursor, limit + 1).await?;
+            let has_newer = posts.len() > limit as usize;
+            if has_newer {
+                posts.remove(0);
+            }
+            (posts, true, has_newer)
+        }
+        (None, None) => {
+            let offset = query.offset.unwrap_or(0);
+            let mut posts = db::posts::get_posts(client, filter, viewer_id, limit + 1, offset).await?;
+            let has_older = posts.len() > limit as usize;
+            posts.truncate(limit as usize);
+            (posts, has_older, offset > 0)
+        }
+    };
+
+    let next_cursor = posts.last().filter(|_| has_older).map(|post| Cursor::from(post).encode());
+    let prev_cursor = posts.first().filter(|_| has_newer).map(|post| Cursor::from(post).encode());
+
+    Ok(PostsList { posts, next_cursor, prev_cursor })
+}
+
+fn bad_request(message: &str) -> Error {
+    ApiError::WithMessage { message: message.to_string(), status_code: StatusCode::BAD_REQUEST }.into()
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchParams {
+    #[validate(length(min = 1, max = 200))]
+    q: String,
+    author_id: Option<Uuid>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    // Cursor from an earlier response
+    cursor: Option<String>,
+    #[validate(range(min = 1, max = 50))]
+    limit: Option<i32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchResults {
+    results: Vec<SearchResult>,
+    // Passed as `cursor` to get the next page. None if there are no more results.
+    next_cursor: Option<String>,
+}
 
-    Ok(HttpResponse::Ok().json(PostsList { posts }))
+#[get("/search")]
+pub async fn search_posts(identity: Identity, data: web::Data<AppState>, query: Query<SearchParams>) -> Result<HttpResponse> {
+    let viewer_id = viewer_id(&identity)?;
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(10);
+
+    let cursor = match &query.cursor {
+        Some(cursor) => match SearchCursor::decode(cursor) {
+            Some(cursor) => Some(cursor),
+            None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid cursor" }))
+        },
+        None => None
+    };
+    let search = PostSearch {
+        query: query.q,
+        author_id: query.author_id,
+        since: query.since,
+        until: query.until,
+    };
+
+    // One extra result is fetched to know if there is another page
+    let mut results = db::posts::search_posts(&data.get_client().await?, &search, viewer_id.as_ref(), cursor.as_ref(), limit + 1).await?;
+    let has_more = results.len() > limit as usize;
+    results.truncate(limit as usize);
+
+    let next_cursor = results.last()
+        .filter(|_| has_more)
+        .map(|result| SearchCursor { rank: result.rank, post_id: result.post.post_id }.encode());
+
+    Ok(HttpResponse::Ok().json(SearchResults { results, next_cursor }))
+}
+
+/// Pushes created and deleted posts as server-sent events. After reconnecting, clients get the events
+/// they missed by sending the id of the last event they got in the `Last-Event-ID` header.
+/// If some of them have already been deleted, a `reset` event tells the client to reload the posts instead.
+#[get("/stream")]
+pub async fn stream_posts(request: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse> {
+    let last_event_id = request.headers().get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i64>().ok());
+    let events = data.post_stream.subscribe(data.pool.clone(), last_event_id).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events))
 }
 
 #[derive(Deserialize, Validate)]
+pub struct ThreadParams {
+    // Levels of replies to include. The direct replies are always included.
+    #[validate(range(min = 1, max = 10))]
+    depth: Option<i32>,
+    #[validate(range(min = 0, max = 50))]
+    limit: Option<i32>,
+    #[validate(range(min = 0, max = 1000))]
+    offset: Option<i32>,
+}
+
+#[get("/{post_id}/thread")]
+pub async fn get_thread(identity: Identity, path: web::Path<PostPath>, data: web::Data<AppState>, query: Query<ThreadParams>) -> Result<HttpResponse> {
+    let viewer_id = viewer_id(&identity)?;
+    let client = data.get_client().await?;
+
+    match db::posts::get_thread(&client, &path.post_id, viewer_id.as_ref(), query.depth.unwrap_or(3), query.limit.unwrap_or(10), query.offset.unwrap_or(0)).await? {
+        Some(thread) => Ok(HttpResponse::Ok().json(thread)),
+        None => Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Post not found" }))
+    }
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
 pub struct CreatePost {
     #[validate(length(min = 1, max = 2000))]
     text: String,
+    parent_post_id: Option<Uuid>,
 }
 
 #[derive(Serialize)]
@@ -51,14 +262,96 @@ struct CreatePostResponse {
     post_id: Uuid
 }
 
+/// Gives the response to send instead of creating or editing a post if the server requires verified emails
+/// and the user has not verified theirs.
+async fn unverified_response(data: &AppState, client: &Client, user_id: &Uuid) -> Result<Option<HttpResponse>, Error> {
+    if !data.require_verified_email {
+        return Ok(None)
+    }
+
+    match db::user::get_user(client, user_id).await? {
+        Some(user) if user.email_verified => Ok(None),
+        Some(_) => Ok(Some(HttpResponse::Forbidden().json(ErrorResponse { error: "Email must be verified before posting" }))),
+        None => Ok(Some(HttpResponse::Unauthorized().json(ErrorResponse { error: "Unauthorized" })))
+    }
+}
+
 #[post("/create")]
-pub async fn create_post(session: Session, data: web::Data<AppState>, body: Json<CreatePost>) -> Result<HttpResponse, Error> {
-    let user_id = require_user(&session)?;
-    let post_id = db::posts::create_post(&data.get_client().await?, &user_id, &body.text).await?;
+pub async fn create_post(identity: Identity, data: web::Data<AppState>, body: Json<CreatePost>) -> Result<HttpResponse, Error> {
+    let user_id = require_scope(&identity, Scope::WritePosts)?;
+    let mut client = data.get_client().await?;
+
+    if let Some(response) = unverified_response(&data, &client, &user_id).await? {
+        return Ok(response)
+    }
+
+    if let Some(parent_post_id) = &body.parent_post_id {
+        if db::posts::get_post(&client, parent_post_id, None).await?.is_none() {
+            return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Parent post not found" }))
+        }
+    }
+
+    let post_id = match db::posts::create_post(&mut client, &user_id, &body.text, body.parent_post_id.as_ref()).await? {
+        Some(post_id) => post_id,
+        None => return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Suspended or banned users cannot post" }))
+    };
 
     Ok(HttpResponse::Ok().json(CreatePostResponse { post_id }))
 }
 
+#[derive(Deserialize)]
+pub struct PostPath {
+    post_id: Uuid,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct EditPost {
+    #[validate(length(min = 1, max = 2000))]
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EditPostResponse {
+    edited_at: DateTime<Utc>
+}
+
+#[patch("/{post_id}")]
+pub async fn edit_post(identity: Identity, path: web::Path<PostPath>, data: web::Data<AppState>, body: Json<EditPost>) -> Result<HttpResponse, Error> {
+    let user_id = require_scope(&identity, Scope::WritePosts)?;
+    let mut client = data.get_client().await?;
+
+    if let Some(response) = unverified_response(&data, &client, &user_id).await? {
+        return Ok(response)
+    }
+
+    match db::posts::post_belongs_to_user(&client, &user_id, &path.post_id).await? {
+        Some(true) => {},
+        Some(false) => return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "You are not the owner of this post" })),
+        None => return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Post not found" }))
+    }
+
+    match db::posts::edit_post(&mut client, &user_id, &path.post_id, &body.text).await? {
+        Some(edited_at) => Ok(HttpResponse::Ok().json(EditPostResponse { edited_at })),
+        None => Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Suspended or banned users cannot edit posts" }))
+    }
+}
+
+#[derive(Serialize)]
+struct RevisionsList {
+    revisions: Vec<PostRevision>
+}
+
+#[get("/{post_id}/revisions")]
+pub async fn get_revisions(identity: Identity, path: web::Path<PostPath>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let client = data.get_client().await?;
+    require_permission(&identity, &client, Permission::ViewPostRevisions).await?;
+
+    let revisions = db::posts::get_revisions(&client, &path.post_id).await?;
+
+    Ok(HttpResponse::Ok().json(RevisionsList { revisions }))
+}
+
 #[derive(Deserialize)]
 pub struct DeletePostData {
     post_id: Uuid,
@@ -70,8 +363,8 @@ struct DeletePostResponse {
 }
 
 #[delete("/delete/{post_id}")]
-pub async fn delete_post(session: Session, path: web::Path<DeletePostData>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
-    let user_id = require_user(&session)?;
+pub async fn delete_post(identity: Identity, path: web::Path<DeletePostData>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let user_id = require_scope(&identity, Scope::WritePosts)?;
     let client = data.get_client().await?;
 
     match db::posts::post_belongs_to_user(&client, &user_id, &path.post_id).await? {
@@ -89,20 +382,33 @@ pub async fn delete_post(session: Session, path: web::Path<DeletePostData>, data
 }
 
 #[delete("/delete/{post_id}/admin")]
-pub async fn delete_post_admin(session: Session, path: web::Path<DeletePostData>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
-    let user_id = require_user(&session)?;
+pub async fn delete_post_admin(req: HttpRequest, identity: Identity, path: web::Path<DeletePostData>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
     let client = data.get_client().await?;
+    let user_id = require_permission(&identity, &client, Permission::DeletePost).await?;
 
-    match db::user::get_user(&client, &user_id).await? {
-        Some(user) => {
-            if !user.admin {
-                return Ok(HttpResponse::Unauthorized().json(ErrorResponse { error: "Unauthorized" }))
-            }
-        }
-        None => return Ok(HttpResponse::Unauthorized().json(ErrorResponse { error: "Unauthorized" }))
+    let owner_id = match db::posts::delete_post_admin(&client, &path.post_id).await? {
+        Some(owner_id) => owner_id,
+        None => return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Post not found" }))
     };
 
-    db::posts::delete_post_admin(&client, &path.post_id).await?;
+    audit::record(&client, &req, EventType::PostDeleted, Some(&user_id), Some(&owner_id), json!({ "post_id": path.post_id })).await?;
 
     Ok(HttpResponse::Ok().json(DeletePostResponse { message: "Post deleted" }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thread_params(depth: i32) -> ThreadParams {
+        ThreadParams { depth: Some(depth), limit: None, offset: None }
+    }
+
+    #[test]
+    fn thread_depth_starts_from_direct_replies() {
+        assert!(thread_params(0).validate().is_err());
+        assert!(thread_params(1).validate().is_ok());
+        assert!(thread_params(10).validate().is_ok());
+        assert!(thread_params(11).validate().is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use actix_session::Session;
+use actix_web::{error, get, HttpResponse, patch, Result, web};
+use actix_web_validator::{Json, Query};
+use deadpool_postgres::Client;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+use webauthn_rs::prelude::Url;
+
+use crate::api::errors::ErrorResponse;
+use crate::api::posts::{list_posts, ListParams, PostsList};
+use crate::api::utilities::{Identity, require_user, viewer_id};
+use crate::db;
+use crate::db::models::Profile;
+use crate::db::posts::PostFilter;
+use crate::db::profiles::ProfileUpdate;
+use crate::handles;
+use crate::models::AppState;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(get_profile_by_handle)
+        .service(get_profile)
+        .service(update_profile);
+}
+
+#[derive(Serialize)]
+struct ProfileResponse {
+    profile: Profile,
+    #[serde(flatten)]
+    posts: PostsList,
+}
+
+#[derive(Deserialize)]
+pub struct UserPath {
+    user_id: Uuid,
+}
+
+#[get("/{user_id}")]
+pub async fn get_profile(identity: Identity, path: web::Path<UserPath>, data: web::Data<AppState>, query: Query<ListParams>) -> Result<HttpResponse, error::Error> {
+    let client = data.get_client().await?;
+    let profile = db::profiles::get_profile(&client, &path.user_id).await?;
+
+    profile_response(&client, profile, &query, viewer_id(&identity)?).await
+}
+
+#[derive(Deserialize)]
+pub struct HandlePath {
+    #[serde(deserialize_with = "handles::deserialize")]
+    handle: String,
+}
+
+#[get("/by-handle/{handle}")]
+pub async fn get_profile_by_handle(identity: Identity, path: web::Path<HandlePath>, data: web::Data<AppState>, query: Query<ListParams>) -> Result<HttpResponse, error::Error> {
+    let client = data.get_client().await?;
+    let profile = db::profiles::get_profile_by_handle(&client, &path.handle).await?;
+
+    profile_response(&client, profile, &query, viewer_id(&identity)?).await
+}
+
+async fn profile_response(client: &Client, profile: Option<Profile>, query: &ListParams, viewer_id: Option<Uuid>) -> Result<HttpResponse, error::Error> {
+    let profile = match profile {
+        Some(profile) => profile,
+        None => return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "User not found" }))
+    };
+
+    let filter = PostFilter { author_id: Some(profile.user_id), ..Default::default() };
+    let posts = list_posts(client, query, &filter, viewer_id.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(ProfileResponse { profile, posts }))
+}
+
+// Other schemes such as javascript: could run code when the avatar is opened
+fn validate_avatar_url(url: &str) -> Result<(), ValidationError> {
+    if url.is_empty() {
+        return Ok(())
+    }
+
+    match Url::parse(url) {
+        Ok(url) if url.scheme() == "https" && url.host().is_some() => Ok(()),
+        _ => Err(ValidationError::new("url"))
+    }
+}
+
+/// Fields that are not given are not changed, and empty strings clear the field.
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateProfileData {
+    #[validate(length(max = 64))]
+    display_name: Option<String>,
+    #[validate(length(max = 500))]
+    bio: Option<String>,
+    #[validate(length(max = 2048), custom = "validate_avatar_url")]
+    avatar_url: Option<String>,
+}
+
+#[patch("/{user_id}")]
+pub async fn update_profile(session: Session, path: web::Path<UserPath>, data: web::Data<AppState>, body: Json<UpdateProfileData>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    if user_id != path.user_id {
+        return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "You can only edit your own profile" }))
+    }
+
+    let client = data.get_client().await?;
+    let body = body.into_inner();
+    let update = ProfileUpdate {
+        display_name: body.display_name.map(|name| name.trim().to_string()),
+        bio: body.bio,
+        avatar_url: body.avatar_url,
+    };
+    db::profiles::update_profile(&client, &user_id, &update).await?;
+
+    match db::profiles::get_profile(&client, &user_id).await? {
+        Some(profile) => Ok(HttpResponse::Ok().json(profile)),
+        None => Ok(HttpResponse::NotFound().json(ErrorResponse { error: "User not found" }))
+    }
+}
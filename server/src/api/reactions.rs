@@ -0,0 +1,127 @@
+use actix_web::{delete, Error, get, HttpResponse, put, Result, web};
+use actix_web_validator::Query;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::api::errors::ErrorResponse;
+use crate::api::utilities::{Identity, require_scope};
+use crate::db;
+use crate::db::models::{Cursor, ReactionUser, Scope};
+use crate::models::AppState;
+
+/// Reactions that can be added to posts. The first one is the like.
+const REACTIONS: [&str; 7] = ["👍", "❤️", "😂", "😮", "😢", "😡", "🎉"];
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(get_reactions)
+        .service(add_reaction)
+        .service(remove_reaction);
+}
+
+/// Finds the reaction in the allowed reactions. Emoji sent without the variation selector are accepted as well.
+fn find_reaction(reaction: &str) -> Option<&'static str> {
+    let reaction = reaction.trim_end_matches('\u{FE0F}');
+
+    REACTIONS.into_iter().find(|allowed| allowed.trim_end_matches('\u{FE0F}') == reaction)
+}
+
+#[derive(Deserialize)]
+pub struct ReactionPath {
+    post_id: Uuid,
+    reaction: String,
+}
+
+#[derive(Serialize)]
+struct ReactionResponse {
+    message: &'static str
+}
+
+#[put("/{post_id}/reactions/{reaction}")]
+pub async fn add_reaction(identity: Identity, path: web::Path<ReactionPath>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let user_id = require_scope(&identity, Scope::WritePosts)?;
+
+    let reaction = match find_reaction(&path.reaction) {
+        Some(reaction) => reaction,
+        None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Unknown reaction" }))
+    };
+
+    match db::reactions::add_reaction(&data.get_client().await?, &user_id, &path.post_id, reaction).await? {
+        Some(true) => Ok(HttpResponse::Ok().json(ReactionResponse { message: "Reaction added" })),
+        Some(false) => Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Suspended or banned users cannot react to posts" })),
+        None => Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Post not found" }))
+    }
+}
+
+#[delete("/{post_id}/reactions/{reaction}")]
+pub async fn remove_reaction(identity: Identity, path: web::Path<ReactionPath>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let user_id = require_scope(&identity, Scope::WritePosts)?;
+
+    let reaction = match find_reaction(&path.reaction) {
+        Some(reaction) => reaction,
+        None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Unknown reaction" }))
+    };
+
+    db::reactions::remove_reaction(&data.get_client().await?, &user_id, &path.post_id, reaction).await?;
+
+    Ok(HttpResponse::Ok().json(ReactionResponse { message: "Reaction removed" }))
+}
+
+#[derive(Deserialize)]
+pub struct PostPath {
+    post_id: Uuid,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ReactionListParams {
+    // Only lists the users who reacted with this reaction
+    reaction: Option<String>,
+    // Cursor from an earlier response
+    before: Option<String>,
+    #[validate(range(min = 1, max = 50))]
+    limit: Option<i32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReactionList {
+    users: Vec<ReactionUser>,
+    // Passed as `before` to get the next page. None if there are no more users.
+    next_cursor: Option<String>,
+}
+
+/// Users who reacted to the post, most recent first
+#[get("/{post_id}/reactions")]
+pub async fn get_reactions(path: web::Path<PostPath>, data: web::Data<AppState>, query: Query<ReactionListParams>) -> Result<HttpResponse, Error> {
+    let client = data.get_client().await?;
+    let limit = query.limit.unwrap_or(20);
+
+    let reaction = match query.reaction.as_deref().map(find_reaction) {
+        Some(Some(reaction)) => Some(reaction),
+        Some(None) => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Unknown reaction" })),
+        None => None
+    };
+    let before = match &query.before {
+        Some(cursor) => match Cursor::decode(cursor) {
+            Some(cursor) => Some(cursor),
+            None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid cursor" }))
+        },
+        None => None
+    };
+
+    if db::posts::get_post(&client, &path.post_id, None).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Post not found" }))
+    }
+
+    // One extra user is fetched to know if there is another page
+    let mut users = db::reactions::get_reactions(&client, &path.post_id, reaction, before.as_ref(), limit + 1).await?;
+    let has_more = users.len() > limit as usize;
+    users.truncate(limit as usize);
+
+    let next_cursor = users.last()
+        .filter(|_| has_more)
+        .map(|user| Cursor::from(user).encode());
+
+    Ok(HttpResponse::Ok().json(ReactionList { users, next_cursor }))
+}
@@ -0,0 +1,91 @@
+use actix_session::Session;
+use actix_web::{delete, error, get, HttpResponse, post, Result, web};
+use actix_web_validator::Json;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::api::errors::ErrorResponse;
+use crate::api::utilities::require_user;
+use crate::db;
+use crate::db::models::{ApiToken, Permission, Scope};
+use crate::models::AppState;
+use crate::tokens::{generate_token, hash_token};
+
+// Makes leaked tokens easy to recognize, e.g. by secret scanners
+const TOKEN_PREFIX: &str = "sp_";
+const TOKEN_LENGTH: usize = 40;
+// The admin scope lets a token use every permission of the user, so only administrators can create one.
+// Moderators use their permissions through the session.
+const ADMIN_SCOPE_PERMISSION: Permission = Permission::ManageRoles;
+
+// Tokens are managed with the session only, so a token cannot be used to create more tokens
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(get_tokens)
+        .service(create_token)
+        .service(delete_token);
+}
+
+#[derive(Serialize)]
+struct TokensList {
+    tokens: Vec<ApiToken>
+}
+
+#[get("")]
+pub async fn get_tokens(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let tokens = db::api_tokens::get_tokens(&data.get_client().await?, &user_id).await?;
+
+    Ok(HttpResponse::Ok().json(TokensList { tokens }))
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateTokenData {
+    #[validate(length(min = 1, max = 64))]
+    name: String,
+    #[validate(length(min = 1, max = 3))]
+    scopes: Vec<Scope>,
+    // The token never expires if this is not given
+    #[validate(range(min = 1, max = 365))]
+    expires_in_days: Option<i32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateTokenResponse {
+    // Only returned here. The token cannot be retrieved later.
+    token: String,
+    api_token: ApiToken,
+}
+
+#[post("")]
+pub async fn create_token(session: Session, data: web::Data<AppState>, body: Json<CreateTokenData>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let client = data.get_client().await?;
+
+    if body.scopes.contains(&Scope::Admin) && !db::roles::has_permission(&client, &user_id, ADMIN_SCOPE_PERMISSION).await? {
+        return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Only administrators can create tokens with the admin scope" }));
+    }
+
+    let token = format!("{}{}", TOKEN_PREFIX, generate_token(TOKEN_LENGTH));
+    let api_token = db::api_tokens::create_token(&client, &user_id, &body.name, &hash_token(&token), &body.scopes, body.expires_in_days).await?;
+
+    Ok(HttpResponse::Ok().json(CreateTokenResponse { token, api_token }))
+}
+
+#[derive(Deserialize)]
+pub struct TokenPath {
+    token_id: Uuid,
+}
+
+#[delete("/{token_id}")]
+pub async fn delete_token(session: Session, path: web::Path<TokenPath>, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+
+    match db::api_tokens::delete_token(&data.get_client().await?, &user_id, &path.token_id).await? {
+        0 => Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Token not found" })),
+        _ => Ok(HttpResponse::Ok().finish())
+    }
+}
@@ -0,0 +1,136 @@
+use actix_session::Session;
+use actix_web::{error, HttpResponse, post, Result, web};
+use actix_web_validator::Json;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::api::errors::{ApiError, ErrorResponse};
+use crate::api::utilities::{check_password, require_user};
+use crate::db;
+use crate::models::AppState;
+use crate::tokens::{generate_token, hash_token};
+use crate::totp;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(setup)
+        .service(confirm)
+        .service(disable);
+}
+
+/// Recovery codes are case-insensitive and may be entered with or without the dash in the middle.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.trim().replace('-', "").to_lowercase()
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = generate_token(RECOVERY_CODE_LENGTH).to_lowercase();
+            let (start, end) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+            format!("{}-{}", start, end)
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SetupResponse {
+    secret: String,
+    provisioning_uri: String,
+}
+
+#[post("/setup")]
+pub async fn setup(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let client = data.get_client().await?;
+
+    let state = match db::totp::get_totp_state(&client, &user_id).await? {
+        Some(state) => state,
+        None => return Err(ApiError::Unauthorized.into())
+    };
+
+    let secret = totp::generate_secret();
+    if !db::totp::set_pending_secret(&client, &user_id, &secret).await? {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Two-factor authentication is already enabled" }))
+    }
+
+    Ok(HttpResponse::Ok().json(SetupResponse {
+        provisioning_uri: totp::provisioning_uri(&secret, &state.email),
+        secret,
+    }))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ConfirmData {
+    #[validate(length(min = 6, max = 6))]
+    code: String,
+    // Asked again so that a stolen session cannot enroll an authenticator and lock the owner out
+    #[validate(length(min = 1, max = 128))]
+    password: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfirmResponse {
+    recovery_codes: Vec<String>,
+}
+
+#[post("/confirm")]
+pub async fn confirm(session: Session, data: web::Data<AppState>, body: Json<ConfirmData>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let mut client = data.get_client().await?;
+
+    if check_password(&data, &client, &user_id, &body.password).await?.is_none() {
+        return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Password invalid" }))
+    }
+
+    let secret = match db::totp::get_totp_state(&client, &user_id).await? {
+        Some(state) if !state.enabled => state.secret,
+        _ => None
+    };
+
+    let secret = match secret {
+        Some(secret) => secret,
+        None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Two-factor authentication setup has not been started" }))
+    };
+
+    let step = match totp::verify_code(&secret, &body.code) {
+        Some(step) => step,
+        None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid code" }))
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    let hashes: Vec<String> = recovery_codes.iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+
+    if !db::totp::enable_totp(&mut client, &user_id, step, &hashes).await? {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid code" }))
+    }
+
+    Ok(HttpResponse::Ok().json(ConfirmResponse { recovery_codes }))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct DisableData {
+    #[validate(length(min = 1, max = 128))]
+    password: String,
+}
+
+#[post("/disable")]
+pub async fn disable(session: Session, data: web::Data<AppState>, body: Json<DisableData>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let mut client = data.get_client().await?;
+
+    if check_password(&data, &client, &user_id, &body.password).await?.is_none() {
+        return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Password invalid" }))
+    }
+
+    db::totp::disable_totp(&mut client, &user_id).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -1,24 +1,39 @@
 use actix_session::Session;
-use actix_web::{delete, error, HttpResponse, post, Result, web};
-use actix_web::http::StatusCode;
+use actix_web::{delete, error, get, HttpRequest, HttpResponse, post, Result, web};
 use actix_web_validator::Json;
+use chrono::Utc;
+use deadpool_postgres::Client;
+use log::debug;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::api::auth::MessageResponse;
 use crate::api::errors::{ApiError, ErrorResponse};
-use crate::api::utilities::{get_session_user, require_user};
+use crate::api::utilities::{allow_account_email, check_password, get_session_public_id, get_session_user, require_user};
+use crate::audit::{self, EventType};
 use crate::db;
-use crate::db::models::User;
+use crate::db::models::{User, UserSession};
 use crate::db::user::get_user;
+use crate::handles;
+use crate::mailer::Email;
 use crate::models::AppState;
+use crate::tokens::{Expiring, sign_token, verify_unexpired_token};
+
+const EMAIL_VERIFICATION_VALID_SECONDS: i64 = 24 * 60 * 60;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg
         .service(authenticate)
         .service(create_account)
         .service(delete_account)
-        .service(change_password);
+        .service(change_password)
+        .service(verify_email)
+        .service(resend_verification_email)
+        .service(get_sessions)
+        .service(revoke_other_sessions)
+        .service(revoke_session);
 }
 
 #[derive(Serialize)]
@@ -46,21 +61,30 @@ pub async fn authenticate(session: Session, data: web::Data<AppState>) -> Result
 #[derive(Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct ChangePasswordData {
-    #[validate(length(min = 1, max = 72))]
+    #[validate(length(min = 1, max = 128))]
     password: String,
-    // Bcrypt max length is 72 bytes
-    #[validate(length(min = 8, max = 72))]
-    new_password: String
+    #[validate(length(min = 8, max = 128))]
+    new_password: String,
+    // Keep other devices signed in after the password change
+    #[serde(default)]
+    keep_other_sessions: bool,
 }
 
 #[post("/changepassword")]
-pub async fn change_password(session: Session, data: web::Data<AppState>, body: Json<ChangePasswordData>) -> Result<HttpResponse, error::Error> {
+pub async fn change_password(req: HttpRequest, session: Session, data: web::Data<AppState>, body: Json<ChangePasswordData>) -> Result<HttpResponse, error::Error> {
     let user_id = require_user(&session)?;
-    let client = data.get_client().await?;
+    let mut client = data.get_client().await?;
+
+    let pwhash = match check_password(&data, &client, &user_id, &body.password).await? {
+        Some(pwhash) => pwhash,
+        None => return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Password invalid" }))
+    };
+    let new_pwhash = data.password_hasher.hash(&body.new_password).await.map_err(ApiError::from)?;
 
-    if db::user::change_password(&client, &user_id, &body.password, &body.new_password).await? {
-        // TODO invalidate other sessions?
+    if db::user::change_password(&mut client, &user_id, &pwhash, &new_pwhash, body.keep_other_sessions).await? {
+        // Renewing saves the current session under a new key, so it survives the deletion of the other sessions
         session.renew();
+        audit::record(&client, &req, EventType::PasswordChanged, Some(&user_id), Some(&user_id), json!({ "keep_other_sessions": body.keep_other_sessions })).await?;
         Ok(HttpResponse::Ok().finish())
     } else {
         Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Password invalid" }))
@@ -71,22 +95,20 @@ pub async fn change_password(session: Session, data: web::Data<AppState>, body:
 pub struct CreateAccountData {
     #[validate(length(min = 1, max = 32))]
     username: String,
-    #[validate(length(min = 8, max = 72))]
+    // Unique name used in profile links and mentions
+    #[serde(deserialize_with = "handles::deserialize")]
+    #[validate(custom = "handles::validate")]
+    handle: String,
+    #[validate(length(min = 8, max = 128))]
     password: String,
     #[validate(email)]
     email: String,
 }
 
 
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-struct CreateAccountResponse {
-    user_id: Uuid,
-}
-
-
 #[post("/createaccount")]
 pub async fn create_account(
+    req: HttpRequest,
     session: Session,
     data: web::Data<AppState>,
     body: Json<CreateAccountData>,
@@ -95,45 +117,219 @@ pub async fn create_account(
         return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Already logged in" }));
     }
 
+    // The password is hashed and one email is sent in both cases, so the response does not reveal if the email is taken
+    let pwhash = data.password_hasher.hash(&body.password).await.map_err(ApiError::from)?;
     let client = data.get_client().await?;
-    let user_id = db::user::create_account(&client, &body.username, &body.email, &body.password).await
-        .map_err(|err| match &err {
-            db::errors::DbError::DuplicateKey => error::Error::from(
-                ApiError::WithMessage { message: "Email already in use".into(), status_code: StatusCode::BAD_REQUEST }
-            ),
-            _ => err.into()
+    match db::user::create_account(&client, &body.username, &body.handle, &body.email, &pwhash).await {
+        Ok(user_id) => {
+            send_verification_email(&data, &client, user_id, &body.email).await?;
+            audit::record(&client, &req, EventType::AccountCreated, None, Some(&user_id), json!({ "email": body.email })).await?;
+        },
+        Err(db::errors::DbError::DuplicateKey) => send_account_exists_email(&data, &client, &body.email).await?,
+        // Handles are public, so telling that one is taken does not reveal anything
+        Err(db::errors::DbError::DuplicateHandle) => return Ok(HttpResponse::Conflict().json(ErrorResponse { error: "Handle is already taken" })),
+        Err(err) => return Err(err.into())
+    }
+
+    Ok(HttpResponse::Ok().json(MessageResponse { message: "Check your email to finish creating your account" }))
+}
+
+/// Not sent if too many emails have been sent to the address recently
+async fn send_account_exists_email(data: &AppState, client: &Client, email: &str) -> Result<(), error::Error> {
+    if !allow_account_email(data, client, email).await? {
+        return Ok(())
+    }
+
+    let link = data.app_url.join("login")
+        .map_err(|err| {
+            debug!("Failed to create login link. {}", err);
+            ApiError::InternalServerError
+        })?;
+
+    db::outbox::queue_email(client, &Email {
+        recipient: email.to_string(),
+        subject: "Account already exists".to_string(),
+        body: format!(
+            "Someone tried to create an account with this email address, but an account already exists. \
+            If it was you, you can log in or reset your password at the address below.\n\n{}\n\nOtherwise you can ignore this email.",
+            link
+        ),
+    }, None).await?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmailVerificationClaims {
+    user_id: Uuid,
+    // The token is only valid for the email it was sent to
+    email: String,
+    expires_at: i64,
+}
+
+impl Expiring for EmailVerificationClaims {
+    fn expires_at(&self) -> i64 {
+        self.expires_at
+    }
+}
+
+/// Returns false if the email was not sent because too many have been sent to the address recently
+pub async fn send_verification_email(data: &AppState, client: &Client, user_id: Uuid, email: &str) -> Result<bool, error::Error> {
+    if !allow_account_email(data, client, email).await? {
+        return Ok(false)
+    }
+
+    let claims = EmailVerificationClaims {
+        user_id,
+        email: email.to_string(),
+        expires_at: Utc::now().timestamp() + EMAIL_VERIFICATION_VALID_SECONDS,
+    };
+
+    let token = sign_token(&data.email_token_key, &claims)
+        .map_err(|err| {
+            debug!("Failed to create email verification token. {}", err);
+            ApiError::InternalServerError
+        })?;
+
+    let mut link = data.app_url.join("verify-email")
+        .map_err(|err| {
+            debug!("Failed to create email verification link. {}", err);
+            ApiError::InternalServerError
         })?;
+    link.query_pairs_mut().append_pair("token", &token);
+
+    db::outbox::queue_email(client, &Email {
+        recipient: email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Use the link below to verify your email address.\n\n{}\n\nThe link expires in {} hours.",
+            link,
+            EMAIL_VERIFICATION_VALID_SECONDS / 60 / 60
+        ),
+    }, Some(EMAIL_VERIFICATION_VALID_SECONDS)).await?;
+
+    Ok(true)
+}
+
+#[derive(Deserialize, Validate)]
+pub struct VerifyEmailData {
+    #[validate(length(min = 1, max = 1024))]
+    token: String,
+}
 
-    session.renew();
-    session.remove("csrf");
-    session.insert("user_id", user_id.clone())?;
+#[post("/verify-email")]
+pub async fn verify_email(data: web::Data<AppState>, body: Json<VerifyEmailData>) -> Result<HttpResponse, error::Error> {
+    let claims = match verify_unexpired_token::<EmailVerificationClaims>(&data.email_token_key, &body.token, Utc::now().timestamp()) {
+        Some(claims) => claims,
+        None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid or expired token" }))
+    };
 
-    Ok(HttpResponse::Ok().json(UserResponse {
-        user: Some(User {
-            user_id,
-            username: body.username.clone(),
-            email: body.email.clone(),
-            admin: false,
-        })
-    }))
+    if !db::user::verify_email(&data.get_client().await?, &claims.user_id, &claims.email).await? {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid or expired token" }))
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[post("/verify-email/resend")]
+pub async fn resend_verification_email(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let client = data.get_client().await?;
+
+    let user = match get_user(&client, &user_id).await? {
+        Some(user) => user,
+        None => return Err(ApiError::Unauthorized.into())
+    };
+
+    if user.email_verified {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Email already verified" }))
+    }
+
+    if !send_verification_email(&data, &client, user_id, &user.email).await? {
+        return Ok(HttpResponse::TooManyRequests().json(ErrorResponse { error: "Too many verification emails have been sent. Try again later." }))
+    }
+
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[derive(Deserialize, Validate)]
 pub struct DeleteAccountBody {
-    #[validate(length(min = 1, max = 72))]
+    #[validate(length(min = 1, max = 128))]
     password: String
 }
 
 #[delete("/deleteaccount")]
-pub async fn delete_account(session: Session, data: web::Data<AppState>, body: Json<DeleteAccountBody>) -> Result<HttpResponse, error::Error> {
+pub async fn delete_account(req: HttpRequest, session: Session, data: web::Data<AppState>, body: Json<DeleteAccountBody>) -> Result<HttpResponse, error::Error> {
     let user_id = require_user(&session)?;
     let client = data.get_client().await?;
 
-    match db::user::delete_account(&client, &user_id, &body.password).await? {
+    let pwhash = match check_password(&data, &client, &user_id, &body.password).await? {
+        Some(pwhash) => pwhash,
+        None => return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Password invalid" }))
+    };
+
+    match db::user::delete_account(&client, &user_id, &pwhash).await? {
         true => {
             session.purge();
+            audit::record(&client, &req, EventType::AccountDeleted, Some(&user_id), Some(&user_id), json!({})).await?;
             Ok(HttpResponse::Ok().finish())
         },
-        false => return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Password invalid" }))
+        false => Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Password invalid" }))
+    }
+}
+
+#[derive(Serialize)]
+struct SessionsList {
+    sessions: Vec<UserSession>
+}
+
+#[get("/sessions")]
+pub async fn get_sessions(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let current_id = get_session_public_id(&session)?;
+    let sessions = db::sessions::get_user_sessions(&data.get_client().await?, &user_id, &current_id).await?;
+
+    Ok(HttpResponse::Ok().json(SessionsList { sessions }))
+}
+
+#[derive(Deserialize)]
+pub struct RevokeSessionData {
+    session_id: Uuid,
+}
+
+#[delete("/sessions/{session_id}")]
+pub async fn revoke_session(session: Session, path: web::Path<RevokeSessionData>, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+
+    if get_session_public_id(&session)? == Some(path.session_id) {
+        session.purge();
+        return Ok(HttpResponse::Ok().finish())
     }
+
+    match db::sessions::delete_user_session(&data.get_client().await?, &user_id, &path.session_id).await? {
+        0 => Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Session not found" })),
+        _ => Ok(HttpResponse::Ok().finish())
+    }
+}
+
+#[delete("/sessions")]
+pub async fn revoke_other_sessions(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+
+    let current_id = match get_session_public_id(&session)? {
+        Some(current_id) => current_id,
+        // Sessions created before public ids existed are saved again with a new id
+        // so that the current session can be told apart from the others
+        None => {
+            let current_id = Uuid::new_v4();
+            session.insert("public_id", current_id)?;
+            session.renew();
+            current_id
+        }
+    };
+
+    db::sessions::delete_other_user_sessions(&data.get_client().await?, &user_id, &current_id).await?;
+
+    Ok(HttpResponse::Ok().finish())
 }
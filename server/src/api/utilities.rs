@@ -1,9 +1,30 @@
-use actix_session::Session;
-use actix_web::Result;
+use std::future::ready;
+
+use actix_session::{Session, SessionExt};
+use actix_web::{error, FromRequest, HttpRequest, HttpResponse, Result, web};
+use actix_web::dev::Payload;
+use actix_web::http::header::{AUTHORIZATION, HeaderMap, RETRY_AFTER, USER_AGENT, X_FORWARDED_FOR};
+use actix_web::http::StatusCode;
+use chrono::Utc;
+use deadpool_postgres::Client;
+use futures_util::future::LocalBoxFuture;
+use futures_util::FutureExt;
 use log::debug;
+use serde::Serialize;
 use uuid::Uuid;
 
-use crate::api::errors::ApiError;
+use crate::api::errors::{ApiError, ErrorResponse};
+use crate::db;
+use crate::db::models::{Permission, Restriction, Scope};
+use crate::models::AppState;
+use crate::proxy::TrustedProxies;
+use crate::tokens::hash_token;
+
+// Longer user agents are truncated before they are saved to the session
+const MAX_USER_AGENT_LENGTH: usize = 512;
+// Time given to enter the second factor after a successful password check
+const PENDING_LOGIN_SECONDS: i64 = 5 * 60;
+const MAX_TWO_FACTOR_ATTEMPTS: u32 = 5;
 
 pub fn get_session_user(session: &Session) -> Result<Option<Uuid>, ApiError> {
     let user_id = session.get::<Uuid>("user_id")
@@ -15,11 +36,342 @@ pub fn get_session_user(session: &Session) -> Result<Option<Uuid>, ApiError> {
     Ok(user_id)
 }
 
-pub fn require_user(session: &Session) -> Result<Uuid, ApiError> {
-    let user_id = get_session_user(&session)?;
+/// Anything that can identify the user making the request.
+pub trait UserSource {
+    fn user_id(&self) -> Result<Option<Uuid>, ApiError>;
+}
+
+impl UserSource for Session {
+    fn user_id(&self) -> Result<Option<Uuid>, ApiError> {
+        get_session_user(self)
+    }
+}
+
+/// The user making the request. Api clients authenticate with an `Authorization: Bearer` token
+/// and browsers with the session cookie. An invalid or expired token is rejected without
+/// falling back to the session.
+pub enum Identity {
+    Session(Session),
+    Token { user_id: Uuid, scopes: Vec<Scope> },
+}
+
+impl UserSource for Identity {
+    fn user_id(&self) -> Result<Option<Uuid>, ApiError> {
+        match self {
+            Identity::Session(session) => get_session_user(session),
+            Identity::Token { user_id, .. } => Ok(Some(*user_id))
+        }
+    }
+}
+
+/// Gets the token from the `Authorization: Bearer <token>` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let (scheme, token) = headers.get(AUTHORIZATION)?
+        .to_str().ok()?
+        .split_once(' ')?;
+    let token = token.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+impl FromRequest for Identity {
+    type Error = error::Error;
+    type Future = LocalBoxFuture<'static, Result<Identity, error::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token_hash = match bearer_token(req.headers()) {
+            Some(token) => hash_token(token),
+            None => return ready(Ok(Identity::Session(req.get_session()))).boxed_local()
+        };
+        let data = req.app_data::<web::Data<AppState>>().cloned();
+
+        async move {
+            let data = data.ok_or(ApiError::InternalServerError)?;
+
+            match db::api_tokens::use_token(&data.get_client().await?, &token_hash).await? {
+                Some((user_id, scopes)) => Ok(Identity::Token { user_id, scopes }),
+                None => Err(ApiError::Unauthorized.into())
+            }
+        }
+        .boxed_local()
+    }
+}
+
+/// Requires a logged-in user. Sessions and tokens of suspended or banned users are never loaded,
+/// so they are rejected here as well.
+pub fn require_user<T: UserSource>(source: &T) -> Result<Uuid, ApiError> {
+    let user_id = source.user_id()?;
 
     match user_id {
         Some(user_id) => Ok(user_id),
         None => Err(ApiError::Forbidden)
     }
 }
+
+/// Gets the public id of the session which can be shown to the user without revealing the session key.
+pub fn get_session_public_id(session: &Session) -> Result<Option<Uuid>, ApiError> {
+    session.get::<Uuid>("public_id")
+        .map_err(|err| {
+            debug!("Failed to get public id from session. {}", err);
+            ApiError::InternalServerError
+        })
+}
+
+/// Ip address of the client. The X-Forwarded-For header is only read from the proxies in TRUSTED_PROXIES,
+/// since other clients can set it to anything. None if the address cannot be resolved.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let forwarded_for: Vec<&str> = req.headers().get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    let peer = req.peer_addr().map(|addr| addr.ip());
+
+    match req.app_data::<web::Data<AppState>>() {
+        Some(data) => data.trusted_proxies.client_ip(peer, &forwarded_for),
+        None => TrustedProxies::default().client_ip(peer, &forwarded_for)
+    }.map(|ip| ip.to_string())
+}
+
+// Emails are compared case-insensitively so that changing the case does not give more attempts
+fn login_account_key(email: &str) -> String {
+    email.to_lowercase()
+}
+
+/// Gives the response to send instead of checking the password or second factor if the account or the ip is locked out.
+/// Only the account is checked if the ip is not known.
+pub async fn lockout_response(req: &HttpRequest, client: &Client, email: &str) -> Result<Option<HttpResponse>, error::Error> {
+    let account_key = login_account_key(email);
+    let ip_key = client_ip(req);
+    let mut keys = vec![(db::login_attempts::ACCOUNT, &account_key)];
+    if let Some(ip_key) = &ip_key {
+        keys.push((db::login_attempts::IP, ip_key));
+    }
+
+    Ok(db::login_attempts::get_lockout_seconds(client, &keys).await?
+        .map(|seconds| HttpResponse::TooManyRequests()
+            .insert_header((RETRY_AFTER, seconds.max(1).to_string()))
+            .json(ErrorResponse { error: "Too many failed login attempts. Try again later." })))
+}
+
+/// Counts a wrong password or second factor against both the account and the ip.
+/// Failures from unknown ips are not grouped together, so they only count against the account.
+pub async fn record_login_failure(req: &HttpRequest, data: &AppState, client: &Client, email: &str) -> Result<(), error::Error> {
+    let throttle = &data.login_throttle;
+
+    db::login_attempts::record_failure(client, db::login_attempts::ACCOUNT, &login_account_key(email), throttle.account_threshold, throttle).await?;
+    if let Some(ip_key) = client_ip(req) {
+        db::login_attempts::record_failure(client, db::login_attempts::IP, &ip_key, throttle.ip_threshold, throttle).await?;
+    }
+
+    Ok(())
+}
+
+/// Forgets the failures of the account once every factor of a login has been verified.
+/// Failures from the ip are not cleared, so that logging in to an own account cannot be used to reset them.
+pub async fn clear_login_failures(client: &Client, email: &str) -> Result<(), error::Error> {
+    db::login_attempts::clear(client, db::login_attempts::ACCOUNT, &login_account_key(email)).await?;
+
+    Ok(())
+}
+
+/// Counts a password reset request against the recipient and the ip. Gives the response to send instead
+/// if either has made too many requests recently. Requests for emails without an account count too,
+/// so the response does not reveal if the account exists.
+pub async fn password_reset_throttle_response(req: &HttpRequest, data: &AppState, client: &Client, email: &str) -> Result<Option<HttpResponse>, error::Error> {
+    let throttle = &data.login_throttle;
+    let email_key = login_account_key(email);
+    let ip_key = client_ip(req);
+    let mut keys = vec![(db::login_attempts::RESET_EMAIL, &email_key)];
+    if let Some(ip_key) = &ip_key {
+        keys.push((db::login_attempts::RESET_IP, ip_key));
+    }
+
+    if let Some(seconds) = db::login_attempts::get_lockout_seconds(client, &keys).await? {
+        return Ok(Some(HttpResponse::TooManyRequests()
+            .insert_header((RETRY_AFTER, seconds.max(1).to_string()))
+            .json(ErrorResponse { error: "Too many password reset requests. Try again later." })));
+    }
+
+    db::login_attempts::record_failure(client, db::login_attempts::RESET_EMAIL, &email_key, throttle.reset_email_threshold, throttle).await?;
+    if let Some(ip_key) = &ip_key {
+        db::login_attempts::record_failure(client, db::login_attempts::RESET_IP, ip_key, throttle.reset_ip_threshold, throttle).await?;
+    }
+
+    Ok(None)
+}
+
+/// Counts an email about the account, such as a verification link, against the address. False if too many have been
+/// sent to it recently, in which case the email should not be sent. Anyone can cause these emails to be sent by signing up
+/// or logging in, so they would otherwise flood the inbox and the outbox.
+pub async fn allow_account_email(data: &AppState, client: &Client, email: &str) -> Result<bool, error::Error> {
+    let throttle = &data.login_throttle;
+    let email_key = login_account_key(email);
+
+    if db::login_attempts::get_lockout_seconds(client, &[(db::login_attempts::ACCOUNT_EMAIL, &email_key)]).await?.is_some() {
+        return Ok(false)
+    }
+
+    db::login_attempts::record_failure(client, db::login_attempts::ACCOUNT_EMAIL, &email_key, throttle.account_email_threshold, throttle).await?;
+
+    Ok(true)
+}
+
+/// Logs the user in by renewing the session and saving the user and client information to it.
+pub fn login_user(req: &HttpRequest, session: &Session, user_id: Uuid) -> Result<(), ApiError> {
+    let ip = client_ip(req);
+    let user_agent = req.headers().get(USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.chars().take(MAX_USER_AGENT_LENGTH).collect::<String>());
+
+    session.renew();
+    session.remove("csrf");
+
+    let mut result = session.insert("user_id", user_id)
+        .and_then(|_| session.insert("public_id", Uuid::new_v4()));
+
+    if let Some(ip) = ip {
+        result = result.and_then(|_| session.insert("ip", ip));
+    }
+
+    if let Some(user_agent) = user_agent {
+        result = result.and_then(|_| session.insert("user_agent", user_agent));
+    }
+
+    result.map_err(|err| {
+        debug!("Failed to insert user data to session. {}", err);
+        ApiError::InternalServerError
+    })
+}
+
+/// Puts the session in a state where the password has been checked, but the second factor is still missing.
+pub fn start_pending_login(session: &Session, user_id: Uuid) -> Result<(), ApiError> {
+    session.renew();
+    session.remove("csrf");
+
+    session.insert("pending_user_id", user_id)
+        .and_then(|_| session.insert("pending_expires_at", Utc::now().timestamp() + PENDING_LOGIN_SECONDS))
+        .and_then(|_| session.insert("pending_attempts", 0u32))
+        .map_err(|err| {
+            debug!("Failed to insert pending login to session. {}", err);
+            ApiError::InternalServerError
+        })
+}
+
+pub fn clear_pending_login(session: &Session) {
+    session.remove("pending_user_id");
+    session.remove("pending_expires_at");
+    session.remove("pending_attempts");
+}
+
+/// Gets the user waiting for two-factor authentication and counts the attempt.
+/// The pending login is cleared if it has expired or too many attempts have been made.
+pub fn get_pending_user(session: &Session) -> Result<Option<Uuid>, ApiError> {
+    let pending = session.get::<Uuid>("pending_user_id")
+        .and_then(|user_id| Ok((
+            user_id,
+            session.get::<i64>("pending_expires_at")?,
+            session.get::<u32>("pending_attempts")?.unwrap_or(0)
+        )))
+        .map_err(|err| {
+            debug!("Failed to get pending login from session. {}", err);
+            ApiError::InternalServerError
+        })?;
+
+    let (user_id, expires_at, attempts) = match pending {
+        (Some(user_id), Some(expires_at), attempts) => (user_id, expires_at, attempts),
+        _ => return Ok(None)
+    };
+
+    if expires_at < Utc::now().timestamp() || attempts >= MAX_TWO_FACTOR_ATTEMPTS {
+        clear_pending_login(session);
+        return Ok(None)
+    }
+
+    session.insert("pending_attempts", attempts + 1)
+        .map_err(|err| {
+            debug!("Failed to update pending login attempts. {}", err);
+            ApiError::InternalServerError
+        })?;
+
+    Ok(Some(user_id))
+}
+
+/// Requires a logged-in user. Tokens must also have been given the scope.
+pub fn require_scope(identity: &Identity, scope: Scope) -> Result<Uuid, ApiError> {
+    let user_id = require_user(identity)?;
+
+    match identity {
+        Identity::Token { scopes, .. } if !scopes.contains(&scope) => Err(ApiError::WithMessage {
+            message: format!("Token does not have the {} scope", scope.as_str()),
+            status_code: StatusCode::FORBIDDEN,
+        }),
+        _ => Ok(user_id)
+    }
+}
+
+/// User the posts are read for, if any. Used for fields such as `reactedByMe` on endpoints that do not require a user.
+/// Tokens without the read scope read posts like anonymous users.
+pub fn viewer_id(identity: &Identity) -> Result<Option<Uuid>, ApiError> {
+    match identity {
+        Identity::Token { scopes, .. } if !scopes.contains(&Scope::ReadPosts) => Ok(None),
+        _ => identity.user_id()
+    }
+}
+
+#[derive(Serialize)]
+struct RestrictedResponse {
+    error: &'static str,
+    restriction: Restriction,
+}
+
+/// Gives the response to send instead of logging in if the user is suspended or banned.
+/// The user must have already proven their identity, since the response reveals the account exists.
+pub async fn restricted_response(client: &Client, user_id: &Uuid) -> Result<Option<HttpResponse>, error::Error> {
+    Ok(db::restrictions::get_restriction(client, user_id).await?
+        .map(|restriction| HttpResponse::Forbidden().json(RestrictedResponse {
+            error: if restriction.banned { "Account has been banned" } else { "Account has been suspended" },
+            restriction,
+        })))
+}
+
+/// Requires the logged-in user to have a role with the permission.
+/// Tokens need the admin scope to use any permission.
+pub async fn require_permission(identity: &Identity, client: &Client, permission: Permission) -> Result<Uuid, error::Error> {
+    let user_id = require_scope(identity, Scope::Admin)?;
+
+    if db::roles::has_permission(client, &user_id, permission).await? {
+        Ok(user_id)
+    } else {
+        Err(ApiError::Unauthorized.into())
+    }
+}
+
+/// Verifies the password against the stored hash. Outdated hashes are transparently replaced
+/// with one using the current parameters. Returns the hash that is stored after the check.
+pub async fn verify_password(data: &AppState, client: &Client, user_id: &Uuid, pwhash: String, password: &str) -> Result<Option<String>, error::Error> {
+    let verification = data.password_hasher.verify(password, &pwhash).await.map_err(ApiError::from)?;
+    if !verification.valid {
+        return Ok(None)
+    }
+
+    if verification.needs_rehash {
+        let new_pwhash = data.password_hasher.hash(password).await.map_err(ApiError::from)?;
+        // Fails if the password was changed at the same time, in which case the old hash is kept
+        if db::user::update_password_hash(client, user_id, &pwhash, &new_pwhash).await? {
+            return Ok(Some(new_pwhash))
+        }
+    }
+
+    Ok(Some(pwhash))
+}
+
+/// Checks the password of the user. Returns the current password hash if the password was correct.
+pub async fn check_password(data: &AppState, client: &Client, user_id: &Uuid, password: &str) -> Result<Option<String>, error::Error> {
+    match db::user::get_password_hash(client, user_id).await? {
+        Some(pwhash) => verify_password(data, client, user_id, pwhash, password).await,
+        None => Ok(None)
+    }
+}
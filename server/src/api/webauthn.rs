@@ -0,0 +1,342 @@
+use actix_session::Session;
+use actix_web::{delete, error, get, HttpRequest, HttpResponse, post, Result, web};
+use actix_web::http::StatusCode;
+use actix_web_validator::Json;
+use data_encoding::BASE64URL_NOPAD;
+use deadpool_postgres::Client;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+use validator::Validate;
+use webauthn_rs::prelude::{
+    DiscoverableAuthentication, DiscoverableKey, PasskeyAuthentication, PasskeyRegistration,
+    PublicKeyCredential, RegisterPublicKeyCredential,
+};
+
+use crate::api::errors::{ApiError, ErrorResponse};
+use crate::api::utilities::{check_password, clear_login_failures, clear_pending_login, get_pending_user, get_session_user, lockout_response, login_user, record_login_failure, require_user, restricted_response};
+use crate::audit::{self, EventType};
+use crate::db;
+use crate::db::models::WebauthnCredential;
+use crate::models::AppState;
+use crate::webauthn::{counter_is_valid, from_webauthn_uuid, to_webauthn_uuid};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(register_start)
+        .service(register_finish)
+        .service(get_credentials)
+        .service(delete_credential)
+        .service(login_start)
+        .service(login_finish)
+        .service(two_factor_start)
+        .service(two_factor_finish);
+}
+
+fn invalid_credential(err: impl std::fmt::Display) -> HttpResponse {
+    debug!("Webauthn ceremony failed. {}", err);
+    HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid credential" })
+}
+
+fn take_state<T: serde::de::DeserializeOwned>(session: &Session, key: &str) -> Result<Option<T>, ApiError> {
+    let state = session.get::<T>(key)
+        .map_err(|err| {
+            debug!("Failed to get webauthn state from session. {}", err);
+            ApiError::InternalServerError
+        })?;
+    // Challenges are single use
+    session.remove(key);
+
+    Ok(state)
+}
+
+#[derive(Deserialize, Validate)]
+pub struct PasswordData {
+    #[validate(length(min = 1, max = 128))]
+    password: String,
+}
+
+/// The current password is asked before registering, so that a stolen session cannot add a passkey
+/// that keeps working after the password is changed. Finishing requires the state saved here.
+#[post("/register/start")]
+pub async fn register_start(session: Session, data: web::Data<AppState>, body: Json<PasswordData>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let client = data.get_client().await?;
+
+    if check_password(&data, &client, &user_id, &body.password).await?.is_none() {
+        return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Password invalid" }))
+    }
+
+    let user = match db::user::get_user(&client, &user_id).await? {
+        Some(user) => user,
+        None => return Err(ApiError::Unauthorized.into())
+    };
+
+    let exclude = db::webauthn::get_passkeys(&client, &user_id).await?
+        .iter()
+        .map(|passkey| passkey.cred_id().clone())
+        .collect();
+
+    let (challenge, state) = match data.webauthn.start_passkey_registration(
+        to_webauthn_uuid(&user_id),
+        &user.email,
+        &user.username,
+        Some(exclude)
+    ) {
+        Ok(v) => v,
+        Err(err) => {
+            debug!("Failed to start passkey registration. {}", err);
+            return Err(ApiError::InternalServerError.into())
+        }
+    };
+
+    session.insert("webauthn_registration", state)?;
+
+    Ok(HttpResponse::Ok().json(challenge))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct RegisterFinishData {
+    #[validate(length(min = 1, max = 64))]
+    name: String,
+    credential: RegisterPublicKeyCredential,
+}
+
+#[post("/register/finish")]
+pub async fn register_finish(session: Session, data: web::Data<AppState>, body: Json<RegisterFinishData>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+
+    let state = match take_state::<PasskeyRegistration>(&session, "webauthn_registration")? {
+        Some(state) => state,
+        None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Registration has not been started" }))
+    };
+
+    let passkey = match data.webauthn.finish_passkey_registration(&body.credential, &state) {
+        Ok(passkey) => passkey,
+        Err(err) => return Ok(invalid_credential(err))
+    };
+
+    db::webauthn::create_credential(&data.get_client().await?, &user_id, &body.name, &passkey).await
+        .map_err(|err| match &err {
+            db::errors::DbError::DuplicateKey => error::Error::from(
+                ApiError::WithMessage { message: "Credential already registered".into(), status_code: StatusCode::BAD_REQUEST }
+            ),
+            _ => err.into()
+        })?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Serialize)]
+struct CredentialsList {
+    credentials: Vec<WebauthnCredential>
+}
+
+#[get("/credentials")]
+pub async fn get_credentials(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let credentials = db::webauthn::get_credentials(&data.get_client().await?, &user_id).await?;
+
+    Ok(HttpResponse::Ok().json(CredentialsList { credentials }))
+}
+
+#[derive(Deserialize)]
+pub struct CredentialPath {
+    credential_id: String,
+}
+
+#[delete("/credentials/{credential_id}")]
+pub async fn delete_credential(session: Session, path: web::Path<CredentialPath>, data: web::Data<AppState>, body: Json<PasswordData>) -> Result<HttpResponse, error::Error> {
+    let user_id = require_user(&session)?;
+    let client = data.get_client().await?;
+
+    if check_password(&data, &client, &user_id, &body.password).await?.is_none() {
+        return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Password invalid" }))
+    }
+
+    let credential_id = match BASE64URL_NOPAD.decode(path.credential_id.as_bytes()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Credential not found" }))
+    };
+
+    match db::webauthn::delete_credential(&client, &user_id, &credential_id).await? {
+        0 => Ok(HttpResponse::NotFound().json(ErrorResponse { error: "Credential not found" })),
+        _ => Ok(HttpResponse::Ok().finish())
+    }
+}
+
+/// Passwordless login using a discoverable credential.
+#[post("/login/start")]
+pub async fn login_start(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    if get_session_user(&session)?.is_some() {
+        return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Already logged in" }));
+    }
+
+    let (challenge, state) = match data.webauthn.start_discoverable_authentication() {
+        Ok(v) => v,
+        Err(err) => {
+            debug!("Failed to start discoverable authentication. {}", err);
+            return Err(ApiError::InternalServerError.into())
+        }
+    };
+
+    session.insert("webauthn_login", state)?;
+
+    Ok(HttpResponse::Ok().json(challenge))
+}
+
+#[derive(Deserialize)]
+pub struct AuthenticateData {
+    credential: PublicKeyCredential,
+}
+
+#[post("/login/finish")]
+pub async fn login_finish(req: HttpRequest, session: Session, data: web::Data<AppState>, body: web::Json<AuthenticateData>) -> Result<HttpResponse, error::Error> {
+    let state = match take_state::<DiscoverableAuthentication>(&session, "webauthn_login")? {
+        Some(state) => state,
+        None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Login has not been started" }))
+    };
+
+    let (user_id, credential_id) = match data.webauthn.identify_discoverable_authentication(&body.credential) {
+        Ok((user_id, credential_id)) => (from_webauthn_uuid(&user_id), credential_id),
+        Err(err) => return Ok(invalid_credential(err))
+    };
+
+    let client = data.get_client().await?;
+    let (mut passkey, stored_counter) = match db::webauthn::get_passkey(&client, &user_id, credential_id).await? {
+        Some(v) => v,
+        None => return Ok(invalid_credential("Unknown credential"))
+    };
+
+    let result = match data.webauthn.finish_discoverable_authentication(&body.credential, state, &[DiscoverableKey::from(&passkey)]) {
+        Ok(result) => result,
+        Err(err) => return Ok(invalid_credential(err))
+    };
+
+    if !counter_is_valid(stored_counter, &result) {
+        return Ok(invalid_credential("Signature counter did not increase"))
+    }
+
+    passkey.update_credential(&result);
+    if !db::webauthn::update_credential(&client, &user_id, &passkey, result.counter()).await? {
+        return Ok(invalid_credential("Signature counter did not increase"))
+    }
+
+    if let Some(response) = restricted_response(&client, &user_id).await? {
+        clear_pending_login(&session);
+        audit::record(&client, &req, EventType::LoginFailed, None, Some(&user_id), json!({ "method": "passkey", "reason": "restricted" })).await?;
+        return Ok(response)
+    }
+
+    let user = match db::user::get_user(&client, &user_id).await? {
+        Some(user) => user,
+        None => return Err(ApiError::Unauthorized.into())
+    };
+
+    // Same as the password login. The passkey proves who the user is, so the reason can be told.
+    if data.require_verified_email && !user.email_verified {
+        audit::record(&client, &req, EventType::LoginFailed, None, Some(&user_id), json!({ "method": "passkey", "reason": "email_not_verified" })).await?;
+        return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "Email must be verified before logging in" }))
+    }
+
+    clear_pending_login(&session);
+    login_user(&req, &session, user.user_id)?;
+    audit::record(&client, &req, EventType::LoginSucceeded, Some(&user.user_id), Some(&user.user_id), json!({ "method": "passkey" })).await?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Starts using a passkey as the second factor of a login that is waiting for one.
+#[post("/2fa/start")]
+pub async fn two_factor_start(session: Session, data: web::Data<AppState>) -> Result<HttpResponse, error::Error> {
+    let user_id = match get_pending_user(&session)? {
+        Some(user_id) => user_id,
+        None => return Ok(HttpResponse::Unauthorized().json(ErrorResponse { error: "No login in progress" }))
+    };
+
+    let passkeys = db::webauthn::get_passkeys(&data.get_client().await?, &user_id).await?;
+    if passkeys.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "No passkeys registered" }))
+    }
+
+    let (challenge, state) = match data.webauthn.start_passkey_authentication(&passkeys) {
+        Ok(v) => v,
+        Err(err) => {
+            debug!("Failed to start passkey authentication. {}", err);
+            return Err(ApiError::InternalServerError.into())
+        }
+    };
+
+    session.insert("webauthn_2fa", state)?;
+
+    Ok(HttpResponse::Ok().json(challenge))
+}
+
+#[post("/2fa/finish")]
+pub async fn two_factor_finish(req: HttpRequest, session: Session, data: web::Data<AppState>, body: web::Json<AuthenticateData>) -> Result<HttpResponse, error::Error> {
+    let user_id = match get_pending_user(&session)? {
+        Some(user_id) => user_id,
+        None => return Ok(HttpResponse::Unauthorized().json(ErrorResponse { error: "No login in progress" }))
+    };
+
+    let state = match take_state::<PasskeyAuthentication>(&session, "webauthn_2fa")? {
+        Some(state) => state,
+        None => return Ok(HttpResponse::BadRequest().json(ErrorResponse { error: "Authentication has not been started" }))
+    };
+
+    let client = data.get_client().await?;
+    let user = match db::user::get_user(&client, &user_id).await? {
+        Some(user) => user,
+        None => return Err(ApiError::Unauthorized.into())
+    };
+
+    if let Some(response) = lockout_response(&req, &client, &user.email).await? {
+        clear_pending_login(&session);
+        audit::record(&client, &req, EventType::LoginFailed, None, Some(&user_id), json!({ "method": "passkey", "reason": "locked_out" })).await?;
+        return Ok(response)
+    }
+
+    if let Err(reason) = verify_passkey(&data, &client, &user_id, &body.credential, &state).await? {
+        record_login_failure(&req, &data, &client, &user.email).await?;
+        audit::record(&client, &req, EventType::LoginFailed, None, Some(&user_id), json!({ "method": "passkey", "reason": "invalid_credential" })).await?;
+        return Ok(invalid_credential(reason))
+    }
+
+    if let Some(response) = restricted_response(&client, &user_id).await? {
+        clear_pending_login(&session);
+        audit::record(&client, &req, EventType::LoginFailed, None, Some(&user_id), json!({ "method": "passkey", "reason": "restricted" })).await?;
+        return Ok(response)
+    }
+
+    clear_pending_login(&session);
+    clear_login_failures(&client, &user.email).await?;
+    login_user(&req, &session, user.user_id)?;
+    audit::record(&client, &req, EventType::LoginSucceeded, Some(&user.user_id), Some(&user.user_id), json!({ "method": "passkey" })).await?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Checks the passkey of the user and updates its signature counter. Gives the reason if the check fails.
+async fn verify_passkey(data: &AppState, client: &Client, user_id: &Uuid, credential: &PublicKeyCredential, state: &PasskeyAuthentication) -> Result<Result<(), String>, error::Error> {
+    let result = match data.webauthn.finish_passkey_authentication(credential, state) {
+        Ok(result) => result,
+        Err(err) => return Ok(Err(err.to_string()))
+    };
+
+    let (mut passkey, stored_counter) = match db::webauthn::get_passkey(client, user_id, result.cred_id()).await? {
+        Some(v) => v,
+        None => return Ok(Err("Unknown credential".to_string()))
+    };
+
+    if !counter_is_valid(stored_counter, &result) {
+        return Ok(Err("Signature counter did not increase".to_string()))
+    }
+
+    passkey.update_credential(&result);
+    if !db::webauthn::update_credential(client, user_id, &passkey, result.counter()).await? {
+        return Ok(Err("Signature counter did not increase".to_string()))
+    }
+
+    Ok(Ok(()))
+}
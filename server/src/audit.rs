@@ -0,0 +1,53 @@
+use actix_web::HttpRequest;
+use deadpool_postgres::Client;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::api::utilities::client_ip;
+use crate::db;
+use crate::db::errors::DbError;
+
+#[derive(Clone, Copy)]
+pub enum EventType {
+    LoginSucceeded,
+    LoginFailed,
+    Logout,
+    PasswordChanged,
+    PasswordReset,
+    AccountCreated,
+    AccountDeleted,
+    // A post deleted by a moderator instead of its owner
+    PostDeleted,
+    CsrfRejected,
+    LockoutCleared,
+    RoleChanged,
+    UserSuspended,
+    UserBanned,
+    RestrictionLifted,
+}
+
+impl EventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::LoginSucceeded => "login_succeeded",
+            EventType::LoginFailed => "login_failed",
+            EventType::Logout => "logout",
+            EventType::PasswordChanged => "password_changed",
+            EventType::PasswordReset => "password_reset",
+            EventType::AccountCreated => "account_created",
+            EventType::AccountDeleted => "account_deleted",
+            EventType::PostDeleted => "post_deleted",
+            EventType::CsrfRejected => "csrf_rejected",
+            EventType::LockoutCleared => "lockout_cleared",
+            EventType::RoleChanged => "role_changed",
+            EventType::UserSuspended => "user_suspended",
+            EventType::UserBanned => "user_banned",
+            EventType::RestrictionLifted => "restriction_lifted",
+        }
+    }
+}
+
+/// Records an event to the audit log. The ip address is taken from the request.
+pub async fn record(client: &Client, req: &HttpRequest, event_type: EventType, actor_id: Option<&Uuid>, target_id: Option<&Uuid>, details: Value) -> Result<(), DbError> {
+    db::audit::insert_event(client, event_type.as_str(), actor_id, target_id, client_ip(req).as_ref(), &details).await
+}
@@ -0,0 +1,79 @@
+use deadpool_postgres::Client;
+use log::debug;
+use uuid::Uuid;
+
+use crate::db::errors::DbError;
+use crate::db::models::{ApiToken, Scope};
+
+pub async fn create_token(client: &Client, user_id: &Uuid, name: &String, token_hash: &String, scopes: &[Scope], expires_in_days: Option<i32>) -> Result<ApiToken, DbError> {
+    let scopes: Vec<&str> = scopes.iter().map(|scope| scope.as_str()).collect();
+
+    let row = client.query_one(
+        // language=postgresql
+        "
+        INSERT INTO api_tokens (user_id, name, token_hash, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP + make_interval(days => $5))
+        RETURNING token_id, name, scopes, created_at, expires_at, last_used_at",
+        &[user_id, name, token_hash, &scopes, &expires_in_days])
+        .await
+        .map_err(|err| {
+            debug!("Error while creating api token. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(ApiToken::from(&row))
+}
+
+pub async fn get_tokens(client: &Client, user_id: &Uuid) -> Result<Vec<ApiToken>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT token_id, name, scopes, created_at, expires_at, last_used_at
+        FROM api_tokens
+        WHERE user_id=$1
+        ORDER BY created_at", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting api tokens. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| ApiToken::from(&row)).collect())
+}
+
+pub async fn delete_token(client: &Client, user_id: &Uuid, token_id: &Uuid) -> Result<u64, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "DELETE FROM api_tokens WHERE user_id=$1 AND token_id=$2", &[user_id, token_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting api token. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result)
+}
+
+/// Gets the owner and scopes of a token that has not expired and marks it as used.
+/// Tokens of suspended or banned users cannot be used.
+pub async fn use_token(client: &Client, token_hash: &String) -> Result<Option<(Uuid, Vec<Scope>)>, DbError> {
+    let row = client.query_opt(
+        // language=postgresql
+        "
+        UPDATE api_tokens t SET last_used_at=CURRENT_TIMESTAMP
+        WHERE token_hash=$1 AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP) AND NOT EXISTS(
+            SELECT 1 FROM users u
+            WHERE u.user_id=t.user_id AND (u.banned OR u.suspended_until > CURRENT_TIMESTAMP)
+        )
+        RETURNING user_id, scopes", &[token_hash])
+        .await
+        .map_err(|err| {
+            debug!("Error while using api token. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.map(|row| (
+        row.get("user_id"),
+        Scope::parse_all(&row.get::<&str, Vec<String>>("scopes"))
+    )))
+}
@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Client;
+use log::debug;
+use uuid::Uuid;
+
+use crate::db::errors::DbError;
+use crate::db::models::AuditEvent;
+
+pub async fn insert_event(client: &Client, event_type: &str, actor_id: Option<&Uuid>, target_id: Option<&Uuid>, ip: Option<&String>, details: &serde_json::Value) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "INSERT INTO audit_events (event_type, actor_id, target_id, ip, details) VALUES ($1, $2, $3, $4, $5)",
+        &[&event_type, &actor_id, &target_id, &ip, details])
+        .await
+        .map_err(|err| {
+            debug!("Error while inserting audit event. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+pub struct AuditFilter {
+    pub event_type: Option<String>,
+    pub actor_id: Option<Uuid>,
+    pub target_id: Option<Uuid>,
+    pub ip: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Gets the newest events matching the filter. Events older than `before` can be fetched by passing
+/// the id of the last returned event.
+pub async fn get_events(client: &Client, filter: &AuditFilter, before: Option<i64>, limit: i64) -> Result<Vec<AuditEvent>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT event_id, event_type, actor_id, target_id, ip, details, created_at
+        FROM audit_events
+        WHERE ($1::TEXT IS NULL OR event_type=$1)
+          AND ($2::uuid IS NULL OR actor_id=$2)
+          AND ($3::uuid IS NULL OR target_id=$3)
+          AND ($4::TEXT IS NULL OR ip=$4)
+          AND ($5::TIMESTAMPTZ IS NULL OR created_at >= $5)
+          AND ($6::TIMESTAMPTZ IS NULL OR created_at < $6)
+          AND ($7::BIGINT IS NULL OR event_id < $7)
+        ORDER BY event_id DESC
+        LIMIT $8",
+        &[&filter.event_type, &filter.actor_id, &filter.target_id, &filter.ip, &filter.since, &filter.until, &before, &limit])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting audit events. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| AuditEvent::from(&row)).collect())
+}
@@ -17,6 +17,9 @@ pub enum DbError {
     #[display(fmt = "Entry already exists")]
     DuplicateKey,
 
+    #[display(fmt = "Handle already taken")]
+    DuplicateHandle,
+
     #[display(fmt = "Failed to connect to db")]
     ConnectError,
 }
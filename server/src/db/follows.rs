@@ -0,0 +1,137 @@
+use deadpool_postgres::Client;
+use log::debug;
+use uuid::Uuid;
+
+use crate::db::errors::DbError;
+use crate::db::models::{Cursor, FollowCounts, FollowUser};
+
+/// Follows the user and notifies them. Following a user again does nothing, and following again
+/// after unfollowing does not notify twice while the first notification is unread.
+pub async fn follow(client: &Client, follower_id: &Uuid, followee_id: &Uuid) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "
+        WITH followed AS (
+            INSERT INTO follows (follower_id, followee_id) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            RETURNING follower_id, followee_id
+        )
+        INSERT INTO notifications (user_id, kind, actor_id)
+        SELECT followee_id, 'follow', follower_id
+        FROM followed f
+        WHERE COALESCE((SELECT follows FROM notification_preferences np WHERE np.user_id=f.followee_id), TRUE)
+          AND NOT EXISTS(
+            SELECT 1 FROM notifications n
+            WHERE n.user_id=f.followee_id AND n.actor_id=f.follower_id AND n.kind='follow' AND n.read_at IS NULL
+          )", &[follower_id, followee_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while following user. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+pub async fn unfollow(client: &Client, follower_id: &Uuid, followee_id: &Uuid) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "DELETE FROM follows WHERE follower_id=$1 AND followee_id=$2", &[follower_id, followee_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while unfollowing user. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+pub async fn get_counts(client: &Client, user_id: &Uuid) -> Result<FollowCounts, DbError> {
+    let row = client.query_one(
+        // language=postgresql
+        "
+        SELECT (SELECT COUNT(*) FROM follows WHERE followee_id=$1) AS followers,
+               (SELECT COUNT(*) FROM follows WHERE follower_id=$1) AS following", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while counting follows. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(FollowCounts {
+        followers: row.get("followers"),
+        following: row.get("following"),
+    })
+}
+
+/// Gets the users following the user, most recent first.
+pub async fn get_followers(client: &Client, user_id: &Uuid, before: Option<&Cursor>, limit: i32) -> Result<Vec<FollowUser>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT u.user_id, u.username, u.handle, f.created_at AS followed_at
+        FROM follows f
+        INNER JOIN users u ON u.user_id = f.follower_id
+        WHERE f.followee_id=$1
+          AND ($2::TIMESTAMPTZ IS NULL OR (f.created_at, f.follower_id) < ($2, $3))
+        ORDER BY f.created_at DESC, f.follower_id DESC
+        LIMIT $4::INT",
+        &[user_id, &before.map(|cursor| cursor.created_at), &before.map(|cursor| cursor.id), &limit])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting followers. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| FollowUser::from(&row)).collect())
+}
+
+/// Gets the users the user follows, most recently followed first.
+pub async fn get_following(client: &Client, user_id: &Uuid, before: Option<&Cursor>, limit: i32) -> Result<Vec<FollowUser>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT u.user_id, u.username, u.handle, f.created_at AS followed_at
+        FROM follows f
+        INNER JOIN users u ON u.user_id = f.followee_id
+        WHERE f.follower_id=$1
+          AND ($2::TIMESTAMPTZ IS NULL OR (f.created_at, f.followee_id) < ($2, $3))
+        ORDER BY f.created_at DESC, f.followee_id DESC
+        LIMIT $4::INT",
+        &[user_id, &before.map(|cursor| cursor.created_at), &before.map(|cursor| cursor.id), &limit])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting followed users. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| FollowUser::from(&row)).collect())
+}
+
+/// Ids of the users who follow the user
+pub async fn get_follower_ids(client: &Client, user_id: &Uuid) -> Result<Vec<Uuid>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "SELECT follower_id FROM follows WHERE followee_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting follower ids. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.iter().map(|row| row.get("follower_id")).collect())
+}
+
+/// Ids of the users the user follows
+pub async fn get_following_ids(client: &Client, user_id: &Uuid) -> Result<Vec<Uuid>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "SELECT followee_id FROM follows WHERE follower_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting followed user ids. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.iter().map(|row| row.get("followee_id")).collect())
+}
@@ -0,0 +1,95 @@
+use deadpool_postgres::Client;
+use log::debug;
+
+use crate::db::errors::DbError;
+use crate::models::LoginThrottleConfig;
+
+pub const ACCOUNT: &str = "account";
+pub const IP: &str = "ip";
+// Password reset requests are limited by the recipient and the ip the same way as failed logins
+pub const RESET_EMAIL: &str = "reset_email";
+pub const RESET_IP: &str = "reset_ip";
+// Emails about the account that anyone can cause to be sent, such as verification links
+pub const ACCOUNT_EMAIL: &str = "account_email";
+
+/// Returns the number of seconds until all of the keys are allowed to try again, or None if none of them are locked.
+pub async fn get_lockout_seconds(client: &Client, keys: &[(&str, &String)]) -> Result<Option<i64>, DbError> {
+    let key_types: Vec<&str> = keys.iter().map(|(key_type, _)| *key_type).collect();
+    let key_values: Vec<&String> = keys.iter().map(|(_, key)| *key).collect();
+
+    let row = client.query_one(
+        // language=postgresql
+        "
+        SELECT CEIL(EXTRACT(EPOCH FROM MAX(locked_until) - CURRENT_TIMESTAMP))::BIGINT AS seconds
+        FROM login_attempts
+        WHERE (key_type, key) IN (SELECT * FROM unnest($1::TEXT[], $2::TEXT[])) AND locked_until > CURRENT_TIMESTAMP",
+        &[&key_types, &key_values])
+        .await
+        .map_err(|err| {
+            debug!("Error while checking login lockout. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.get("seconds"))
+}
+
+/// Records a failed login or a password reset request. Once the failures reach the threshold, the key is locked for a time
+/// that doubles with each further failure. Failures are forgotten after the reset window.
+pub async fn record_failure(client: &Client, key_type: &str, key: &String, threshold: i32, config: &LoginThrottleConfig) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "
+        INSERT INTO login_attempts AS a (key_type, key, failures, last_failure_at, locked_until)
+        VALUES ($1, $2, 1, CURRENT_TIMESTAMP,
+            CASE WHEN $3 <= 1 THEN CURRENT_TIMESTAMP + LEAST($5::BIGINT, $4::BIGINT) * INTERVAL '1 second' END)
+        ON CONFLICT (key_type, key) DO UPDATE SET
+            failures = CASE
+                WHEN a.last_failure_at < CURRENT_TIMESTAMP - $6::BIGINT * INTERVAL '1 second' THEN 1
+                ELSE a.failures + 1
+            END,
+            last_failure_at = CURRENT_TIMESTAMP,
+            locked_until = CASE
+                WHEN a.last_failure_at >= CURRENT_TIMESTAMP - $6::BIGINT * INTERVAL '1 second' AND a.failures + 1 >= $3
+                    THEN CURRENT_TIMESTAMP + LEAST($5::BIGINT, $4::BIGINT * POWER(2, LEAST(a.failures + 1 - $3, 30))::BIGINT) * INTERVAL '1 second'
+                ELSE a.locked_until
+            END",
+        &[&key_type, key, &threshold, &config.base_lockout_seconds, &config.max_lockout_seconds, &config.reset_after_seconds])
+        .await
+        .map_err(|err| {
+            debug!("Error while recording failed login. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+pub async fn clear(client: &Client, key_type: &str, key: &String) -> Result<u64, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "DELETE FROM login_attempts WHERE key_type=$1 AND key=$2", &[&key_type, key])
+        .await
+        .map_err(|err| {
+            debug!("Error while clearing login attempts. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result)
+}
+
+/// Deletes the keys whose failures would already be forgotten and that are not locked.
+/// Called periodically so that the table does not keep a row for every ip and email ever tried.
+pub async fn delete_expired(client: &Client, reset_after_seconds: i64) -> Result<u64, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "
+        DELETE FROM login_attempts
+        WHERE last_failure_at < CURRENT_TIMESTAMP - $1::BIGINT * INTERVAL '1 second'
+          AND (locked_until IS NULL OR locked_until <= CURRENT_TIMESTAMP)", &[&reset_after_seconds])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting expired login attempts. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result)
+}
@@ -2,4 +2,18 @@ pub mod session_store;
 pub mod models;
 pub mod user;
 pub mod errors;
+pub mod api_tokens;
+pub mod audit;
+pub mod follows;
+pub mod login_attempts;
+pub mod notifications;
+pub mod outbox;
+pub mod password_reset;
 pub mod posts;
+pub mod reactions;
+pub mod profiles;
+pub mod restrictions;
+pub mod roles;
+pub mod sessions;
+pub mod totp;
+pub mod webauthn;
@@ -1,33 +1,96 @@
-use chrono::{DateTime, Utc};
-use serde::Serialize;
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
 use tokio_postgres::Row;
+use tokio_postgres::types::Json;
 use uuid::Uuid;
+use validator::Validate;
+
+use crate::entities::{self, Entity};
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct User {
     pub user_id: Uuid,
     pub username: String,
+    pub handle: String,
     pub email: String,
-    pub admin: bool,
+    pub role: String,
+    pub permissions: Vec<Permission>,
+    pub two_factor_enabled: bool,
+    // Second factors that can be used to log in. Recovery codes can be used when TOTP is enabled.
+    pub two_factor_methods: Vec<TwoFactorMethod>,
+    pub email_verified: bool,
 }
 
 impl From<&Row> for User {
     fn from(row: &Row) -> Self {
+        let two_factor_methods: Vec<TwoFactorMethod> = [
+            (TwoFactorMethod::Totp, row.get::<&str, bool>("totp_enabled")),
+            (TwoFactorMethod::Passkey, row.get::<&str, bool>("has_passkeys")),
+        ]
+            .into_iter()
+            .filter_map(|(method, enabled)| enabled.then_some(method))
+            .collect();
+
         Self {
             user_id: row.get("user_id"),
             username: row.get("username"),
+            handle: row.get("handle"),
             email: row.get("email"),
-            admin: row.get("admin"),
+            role: row.get("role"),
+            permissions: Permission::parse_all(&row.get::<&str, Vec<String>>("permissions")),
+            two_factor_enabled: !two_factor_methods.is_empty(),
+            two_factor_methods,
+            email_verified: row.get("email_verified"),
         }
     }
 }
 
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TwoFactorMethod {
+    Totp,
+    Passkey,
+}
+
+/// Information about a user that anyone can see. Must not contain the email or other private data.
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub user_id: Uuid,
+    pub username: String,
+    pub handle: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_url: Option<String>,
+    pub joined_at: DateTime<Utc>,
+    pub post_count: i64,
+}
+
+impl From<&Row> for Profile {
+    fn from(row: &Row) -> Self {
+        Self {
+            user_id: row.get("user_id"),
+            username: row.get("username"),
+            handle: row.get("handle"),
+            display_name: row.get("display_name"),
+            bio: row.get("bio"),
+            avatar_url: row.get("avatar_url"),
+            joined_at: row.get("joined_at"),
+            post_count: row.get("post_count"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct PostUser {
     pub user_id: Uuid,
     pub username: String,
+    pub handle: String,
 }
 
 #[derive(Serialize)]
@@ -36,20 +99,599 @@ pub struct Post {
     pub user: PostUser,
     pub post_id: Uuid,
     pub timestamp: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
     pub text: String,
+    pub parent_post_id: Option<Uuid>,
+    pub root_post_id: Option<Uuid>,
+    pub reply_count: i64,
+    pub entities: Vec<Entity>,
+    pub reactions: Vec<ReactionCount>,
 }
 
 impl From<&Row> for Post {
     fn from(row: &Row) -> Self {
+        let text: String = row.get("text");
+        let mentioned: Json<HashMap<String, Uuid>> = row.get("mentions");
+        let reactions: Json<Vec<ReactionCount>> = row.get("reactions");
+
         Self {
             user: PostUser {
                 user_id: row.get("user_id"),
-                username: row.get("username")
+                username: row.get("username"),
+                handle: row.get("handle"),
             },
             post_id: row.get("post_id"),
-            timestamp: row.get("timestamp"),
+            timestamp: row.get("created_at"),
+            edited_at: row.get("edited_at"),
+            entities: entities::parse(&text, &mentioned.0),
+            text,
+            parent_post_id: row.get("parent_post_id"),
+            root_post_id: row.get("root_post_id"),
+            reply_count: row.get("reply_count"),
+            reactions: reactions.0,
+        }
+    }
+}
+
+/// Number of users who reacted to a post with the reaction
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionCount {
+    pub reaction: String,
+    pub count: i64,
+    // Always false for anonymous requests and streamed posts. Set by `db::reactions::mark_own_reactions`.
+    #[serde(default)]
+    pub reacted_by_me: bool,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PostEventKind {
+    Created,
+    Deleted,
+}
+
+impl PostEventKind {
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "created" => Some(PostEventKind::Created),
+            "deleted" => Some(PostEventKind::Deleted),
+            _ => None
+        }
+    }
+}
+
+/// Created or deleted post sent to the post stream
+pub struct PostEvent {
+    pub event_id: i64,
+    pub kind: PostEventKind,
+    pub post_id: Uuid,
+    // Only set for created posts
+    pub post: Option<Post>,
+}
+
+impl PostEvent {
+    /// None if the kind is not known
+    pub fn from_row(row: &Row) -> Option<Self> {
+        let kind = PostEventKind::parse(row.get("kind"))?;
+        // The post columns are null if the post has been deleted since
+        let exists = row.get::<_, Option<Uuid>>("user_id").is_some();
+
+        Some(Self {
+            event_id: row.get("event_id"),
+            post_id: row.get("event_post_id"),
+            post: (kind == PostEventKind::Created && exists).then(|| Post::from(row)),
+            kind,
+        })
+    }
+}
+
+/// Position of a row in a list ordered by time, used as an opaque pagination cursor
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        BASE64URL_NOPAD.encode(format!("{}:{}", self.created_at.timestamp_micros(), self.id).as_bytes())
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let decoded = String::from_utf8(BASE64URL_NOPAD.decode(cursor.as_bytes()).ok()?).ok()?;
+        let (micros, id) = decoded.split_once(':')?;
+        let created_at = NaiveDateTime::from_timestamp_micros(micros.parse().ok()?)?;
+
+        Some(Self {
+            created_at: DateTime::from_utc(created_at, Utc),
+            id: Uuid::parse_str(id).ok()?,
+        })
+    }
+}
+
+impl From<&Post> for Cursor {
+    fn from(post: &Post) -> Self {
+        Self {
+            created_at: post.timestamp,
+            id: post.post_id,
+        }
+    }
+}
+
+/// Position of a search result, used as an opaque pagination cursor
+pub struct SearchCursor {
+    pub rank: f32,
+    pub post_id: Uuid,
+}
+
+impl SearchCursor {
+    pub fn encode(&self) -> String {
+        BASE64URL_NOPAD.encode(format!("{}:{}", self.rank, self.post_id).as_bytes())
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let decoded = String::from_utf8(BASE64URL_NOPAD.decode(cursor.as_bytes()).ok()?).ok()?;
+        let (rank, post_id) = decoded.split_once(':')?;
+
+        Some(Self {
+            rank: rank.parse().ok().filter(|rank: &f32| rank.is_finite())?,
+            post_id: Uuid::parse_str(post_id).ok()?,
+        })
+    }
+}
+
+/// Part of a search result snippet. Matching words are highlighted.
+#[derive(Serialize)]
+pub struct SnippetPart {
+    pub text: String,
+    pub highlight: bool,
+}
+
+// Mark the matches in the snippets returned by the search query, chr(2) and chr(3) in SQL
+const HIGHLIGHT_START: char = '\u{2}';
+const HIGHLIGHT_END: char = '\u{3}';
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub post: Post,
+    pub rank: f32,
+    pub snippet: Vec<SnippetPart>,
+}
+
+impl From<&Row> for SearchResult {
+    fn from(row: &Row) -> Self {
+        let mut snippet = Vec::new();
+        let mut part = SnippetPart { text: String::new(), highlight: false };
+        for c in row.get::<&str, &str>("snippet").chars() {
+            let highlight = match c {
+                HIGHLIGHT_START => true,
+                HIGHLIGHT_END => false,
+                _ => {
+                    part.text.push(c);
+                    continue
+                }
+            };
+            let next = SnippetPart { text: String::new(), highlight };
+            let done = std::mem::replace(&mut part, next);
+            if !done.text.is_empty() {
+                snippet.push(done);
+            }
+        }
+        if !part.text.is_empty() {
+            snippet.push(part);
+        }
+
+        Self {
+            post: Post::from(row),
+            rank: row.get("rank"),
+            snippet,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FollowUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub handle: String,
+    pub followed_at: DateTime<Utc>,
+}
+
+impl From<&Row> for FollowUser {
+    fn from(row: &Row) -> Self {
+        Self {
+            user_id: row.get("user_id"),
+            username: row.get("username"),
+            handle: row.get("handle"),
+            followed_at: row.get("followed_at"),
+        }
+    }
+}
+
+impl From<&FollowUser> for Cursor {
+    fn from(user: &FollowUser) -> Self {
+        Self {
+            created_at: user.followed_at,
+            id: user.user_id,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub handle: String,
+    pub reaction: String,
+    pub reacted_at: DateTime<Utc>,
+}
+
+impl From<&Row> for ReactionUser {
+    fn from(row: &Row) -> Self {
+        Self {
+            user_id: row.get("user_id"),
+            username: row.get("username"),
+            handle: row.get("handle"),
+            reaction: row.get("reaction"),
+            reacted_at: row.get("reacted_at"),
+        }
+    }
+}
+
+impl From<&ReactionUser> for Cursor {
+    fn from(user: &ReactionUser) -> Self {
+        Self {
+            created_at: user.reacted_at,
+            id: user.user_id,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FollowCounts {
+    pub followers: i64,
+    pub following: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrendingTag {
+    pub tag: String,
+    pub post_count: i64,
+    pub user_count: i64,
+}
+
+impl From<&Row> for TrendingTag {
+    fn from(row: &Row) -> Self {
+        Self {
+            tag: row.get("tag"),
+            post_count: row.get("post_count"),
+            user_count: row.get("user_count"),
+        }
+    }
+}
+
+/// A post with the replies below it
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadPost {
+    #[serde(flatten)]
+    pub post: Post,
+    pub replies: Vec<ThreadPost>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSession {
+    pub session_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub current: bool,
+}
+
+impl From<&Row> for UserSession {
+    fn from(row: &Row) -> Self {
+        Self {
+            session_id: row.get("public_id"),
+            created_at: row.get("created_at"),
+            last_seen_at: row.get("last_seen_at"),
+            ip: row.get("ip"),
+            user_agent: row.get("user_agent"),
+            current: row.get("current"),
+        }
+    }
+}
+
+pub struct TotpState {
+    pub email: String,
+    pub secret: Option<String>,
+    pub enabled: bool,
+}
+
+impl From<&Row> for TotpState {
+    fn from(row: &Row) -> Self {
+        Self {
+            email: row.get("email"),
+            secret: row.get("totp_secret"),
+            enabled: row.get("totp_enabled"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnCredential {
+    pub credential_id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<&Row> for WebauthnCredential {
+    fn from(row: &Row) -> Self {
+        Self {
+            credential_id: BASE64URL_NOPAD.encode(row.get::<&str, &[u8]>("credential_id")),
+            name: row.get("name"),
+            created_at: row.get("created_at"),
+            last_used_at: row.get("last_used_at"),
+        }
+    }
+}
+
+/// Permissions that can be given to an api token. Logged-in sessions have all of them.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scope {
+    #[serde(rename = "posts:read")]
+    ReadPosts,
+    #[serde(rename = "posts:write")]
+    WritePosts,
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ReadPosts => "posts:read",
+            Scope::WritePosts => "posts:write",
+            Scope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Scope> {
+        match scope {
+            "posts:read" => Some(Scope::ReadPosts),
+            "posts:write" => Some(Scope::WritePosts),
+            "admin" => Some(Scope::Admin),
+            _ => None
+        }
+    }
+
+    /// Parses scopes stored in the database. Unknown scopes are ignored.
+    pub fn parse_all(scopes: &[String]) -> Vec<Scope> {
+        scopes.iter().filter_map(|scope| Scope::parse(scope)).collect()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<&Row> for ApiToken {
+    fn from(row: &Row) -> Self {
+        Self {
+            token_id: row.get("token_id"),
+            name: row.get("name"),
+            scopes: Scope::parse_all(&row.get::<&str, Vec<String>>("scopes")),
+            created_at: row.get("created_at"),
+            expires_at: row.get("expires_at"),
+            last_used_at: row.get("last_used_at"),
+        }
+    }
+}
+
+/// Actions that are only allowed for some roles. Which roles have which permissions is stored in the database.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    // Delete posts of other users
+    DeletePost,
+    ClearLockouts,
+    ManageRoles,
+    // Suspend and ban users
+    RestrictUsers,
+    ViewAuditLog,
+    // See the earlier versions of edited posts
+    ViewPostRevisions,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::DeletePost => "delete_post",
+            Permission::ClearLockouts => "clear_lockouts",
+            Permission::ManageRoles => "manage_roles",
+            Permission::RestrictUsers => "restrict_users",
+            Permission::ViewAuditLog => "view_audit_log",
+            Permission::ViewPostRevisions => "view_post_revisions",
+        }
+    }
+
+    pub fn parse(permission: &str) -> Option<Permission> {
+        match permission {
+            "delete_post" => Some(Permission::DeletePost),
+            "clear_lockouts" => Some(Permission::ClearLockouts),
+            "manage_roles" => Some(Permission::ManageRoles),
+            "restrict_users" => Some(Permission::RestrictUsers),
+            "view_audit_log" => Some(Permission::ViewAuditLog),
+            "view_post_revisions" => Some(Permission::ViewPostRevisions),
+            _ => None
+        }
+    }
+
+    /// Parses permissions stored in the database. Permissions the application does not know of are ignored.
+    pub fn parse_all(permissions: &[String]) -> Vec<Permission> {
+        permissions.iter().filter_map(|permission| Permission::parse(permission)).collect()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Role {
+    pub role: String,
+    pub permissions: Vec<Permission>,
+}
+
+impl From<&Row> for Role {
+    fn from(row: &Row) -> Self {
+        Self {
+            role: row.get("role"),
+            permissions: Permission::parse_all(&row.get::<&str, Vec<String>>("permissions")),
+        }
+    }
+}
+
+/// An active suspension or ban of a user.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Restriction {
+    pub banned: bool,
+    pub suspended_until: Option<DateTime<Utc>>,
+    pub reason: Option<String>,
+}
+
+impl From<&Row> for Restriction {
+    fn from(row: &Row) -> Self {
+        Self {
+            banned: row.get("banned"),
+            suspended_until: row.get("suspended_until"),
+            reason: row.get("restriction_reason"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub event_id: i64,
+    pub event_type: String,
+    pub actor_id: Option<Uuid>,
+    pub target_id: Option<Uuid>,
+    pub ip: Option<String>,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&Row> for AuditEvent {
+    fn from(row: &Row) -> Self {
+        Self {
+            event_id: row.get("event_id"),
+            event_type: row.get("event_type"),
+            actor_id: row.get("actor_id"),
+            target_id: row.get("target_id"),
+            ip: row.get("ip"),
+            details: row.get("details"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostRevision {
+    pub revision_id: i64,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+    pub replaced_at: DateTime<Utc>,
+}
+
+impl From<&Row> for PostRevision {
+    fn from(row: &Row) -> Self {
+        Self {
+            revision_id: row.get("revision_id"),
             text: row.get("text"),
+            created_at: row.get("created_at"),
+            replaced_at: row.get("replaced_at"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    Mention,
+    Reply,
+    Follow,
+}
+
+impl NotificationKind {
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "mention" => Some(NotificationKind::Mention),
+            "reply" => Some(NotificationKind::Reply),
+            "follow" => Some(NotificationKind::Follow),
+            _ => None
         }
     }
 }
 
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub notification_id: i64,
+    pub kind: NotificationKind,
+    pub actor: PostUser,
+    // The post with the mention or the reply
+    pub post_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub read: bool,
+}
+
+impl Notification {
+    /// None if the kind is not known
+    pub fn from_row(row: &Row) -> Option<Self> {
+        Some(Self {
+            notification_id: row.get("notification_id"),
+            kind: NotificationKind::parse(row.get("kind"))?,
+            actor: PostUser {
+                user_id: row.get("actor_id"),
+                username: row.get("username"),
+                handle: row.get("handle"),
+            },
+            post_id: row.get("post_id"),
+            created_at: row.get("created_at"),
+            read: row.get("read"),
+        })
+    }
+}
+
+/// Which notifications the user wants to get
+#[derive(Serialize, Deserialize, Validate)]
+pub struct NotificationPreferences {
+    pub mentions: bool,
+    pub replies: bool,
+    pub follows: bool,
+}
+
+impl From<&Row> for NotificationPreferences {
+    fn from(row: &Row) -> Self {
+        Self {
+            mentions: row.get("mentions"),
+            replies: row.get("replies"),
+            follows: row.get("follows"),
+        }
+    }
+}
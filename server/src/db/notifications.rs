@@ -0,0 +1,139 @@
+use deadpool_postgres::Client;
+use log::debug;
+use uuid::Uuid;
+
+use crate::db::errors::DbError;
+use crate::db::models::{Notification, NotificationPreferences};
+
+/// Gets the notifications of the user, newest first.
+pub async fn get_notifications(client: &Client, user_id: &Uuid, unread_only: bool, before: Option<i64>, limit: i64) -> Result<Vec<Notification>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT n.notification_id, n.kind, n.actor_id, u.username, u.handle, n.post_id, n.created_at, n.read_at IS NOT NULL AS read
+        FROM notifications n
+        INNER JOIN users u ON u.user_id = n.actor_id
+        WHERE n.user_id=$1
+          AND (NOT $2 OR n.read_at IS NULL)
+          AND ($3::BIGINT IS NULL OR n.notification_id < $3)
+        ORDER BY n.notification_id DESC
+        LIMIT $4", &[user_id, &unread_only, &before, &limit])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting notifications. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.iter().filter_map(Notification::from_row).collect())
+}
+
+/// Gets at most `limit` notifications newer than `after`, oldest first, with the users they belong to.
+/// Notifications of all users are returned if `user_id` is None.
+pub async fn get_new_notifications(client: &Client, user_id: Option<&Uuid>, after: i64, limit: i64) -> Result<Vec<(Uuid, Notification)>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT n.notification_id, n.user_id, n.kind, n.actor_id, u.username, u.handle, n.post_id, n.created_at, n.read_at IS NOT NULL AS read
+        FROM notifications n
+        INNER JOIN users u ON u.user_id = n.actor_id
+        WHERE ($1::uuid IS NULL OR n.user_id=$1) AND n.notification_id > $2
+        ORDER BY n.notification_id
+        LIMIT $3", &[&user_id, &after, &limit])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting new notifications. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.iter()
+        .filter_map(|row| Some((row.get("user_id"), Notification::from_row(row)?)))
+        .collect())
+}
+
+/// Id of the newest notification of any user, or 0 if there are none
+pub async fn get_last_notification_id(client: &Client) -> Result<i64, DbError> {
+    let row = client.query_one(
+        // language=postgresql
+        "SELECT COALESCE(MAX(notification_id), 0) AS notification_id FROM notifications", &[])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting the last notification. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.get("notification_id"))
+}
+
+pub async fn count_unread(client: &Client, user_id: &Uuid) -> Result<i64, DbError> {
+    let row = client.query_one(
+        // language=postgresql
+        "SELECT COUNT(*) AS unread FROM notifications WHERE user_id=$1 AND read_at IS NULL", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while counting unread notifications. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.get("unread"))
+}
+
+/// Returns false if the user has no such notification.
+pub async fn mark_read(client: &Client, user_id: &Uuid, notification_id: i64) -> Result<bool, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "
+        UPDATE notifications SET read_at=COALESCE(read_at, CURRENT_TIMESTAMP)
+        WHERE user_id=$1 AND notification_id=$2", &[user_id, &notification_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while marking notification read. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result == 1)
+}
+
+pub async fn mark_all_read(client: &Client, user_id: &Uuid) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "UPDATE notifications SET read_at=CURRENT_TIMESTAMP WHERE user_id=$1 AND read_at IS NULL", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while marking notifications read. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+pub async fn get_preferences(client: &Client, user_id: &Uuid) -> Result<NotificationPreferences, DbError> {
+    let row = client.query_opt(
+        // language=postgresql
+        "SELECT mentions, replies, follows FROM notification_preferences WHERE user_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting notification preferences. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(match row {
+        Some(row) => NotificationPreferences::from(&row),
+        None => NotificationPreferences { mentions: true, replies: true, follows: true }
+    })
+}
+
+pub async fn set_preferences(client: &Client, user_id: &Uuid, preferences: &NotificationPreferences) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "
+        INSERT INTO notification_preferences (user_id, mentions, replies, follows) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id) DO UPDATE SET mentions=$2, replies=$3, follows=$4",
+        &[user_id, &preferences.mentions, &preferences.replies, &preferences.follows])
+        .await
+        .map_err(|err| {
+            debug!("Error while saving notification preferences. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use deadpool_postgres::Client;
+use log::debug;
+
+use crate::db::errors::DbError;
+use crate::mailer::{Email, Mailer, MAX_SEND_ATTEMPTS};
+
+/// Queues the email for sending. An email with a link that stops working should be given its validity,
+/// so that it is deleted if it has not been sent by then.
+pub async fn queue_email(client: &Client, email: &Email, valid_seconds: Option<i64>) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "
+        INSERT INTO email_outbox (recipient, subject, body, expires_at)
+        VALUES ($1, $2, $3, CURRENT_TIMESTAMP + $4::BIGINT * INTERVAL '1 second')",
+        &[&email.recipient, &email.subject, &email.body, &valid_seconds])
+        .await
+        .map_err(|err| {
+            debug!("Error while queueing email. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+/// Deletes the unsent emails whose links have expired.
+pub async fn delete_expired_emails(client: &Client) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "DELETE FROM email_outbox WHERE sent_at IS NULL AND expires_at <= CURRENT_TIMESTAMP", &[])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting expired emails. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+/// Sends a batch of pending emails. The body of a sent email is cleared, since it may contain a secret link. The emails are claimed in a single statement before sending, so that
+/// multiple server instances do not send the same email twice and no rows are locked while talking to the mail server.
+/// An email whose claim is older than `claim_timeout_seconds` is claimed again, so it may be sent twice if
+/// an instance stops between sending it and marking it as sent.
+pub async fn send_pending_emails(client: &Client, mailer: &dyn Mailer, limit: i64, claim_timeout_seconds: i64) -> Result<(), DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        UPDATE email_outbox SET claimed_at=CURRENT_TIMESTAMP
+        WHERE email_id IN (
+            SELECT email_id
+            FROM email_outbox
+            WHERE sent_at IS NULL AND attempts < $1
+              AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+              AND (claimed_at IS NULL OR claimed_at < CURRENT_TIMESTAMP - $3::BIGINT * INTERVAL '1 second')
+            ORDER BY created_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING email_id, recipient, subject, body", &[&MAX_SEND_ATTEMPTS, &limit, &claim_timeout_seconds])
+        .await
+        .map_err(|err| {
+            debug!("Error while claiming pending emails. {}", err);
+            DbError::InternalError
+        })?;
+
+    for row in rows {
+        let email_id: uuid::Uuid = row.get("email_id");
+        let email = Email {
+            recipient: row.get("recipient"),
+            subject: row.get("subject"),
+            body: row.get("body"),
+        };
+
+        let result = match mailer.send(&email).await {
+            Ok(_) => client.execute(
+                // language=postgresql
+                "UPDATE email_outbox SET sent_at=CURRENT_TIMESTAMP, claimed_at=NULL, body='', attempts=attempts + 1, last_error=NULL WHERE email_id=$1",
+                &[&email_id]).await,
+            Err(err) => {
+                debug!("Failed to send email {}. {}", email_id, err);
+                client.execute(
+                    // language=postgresql
+                    "UPDATE email_outbox SET claimed_at=NULL, attempts=attempts + 1, last_error=$2 WHERE email_id=$1",
+                    &[&email_id, &err.to_string()]).await
+            }
+        };
+
+        result.map_err(|err| {
+            debug!("Error while updating email status. {}", err);
+            DbError::InternalError
+        })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use deadpool_postgres::Client;
+use log::debug;
+
+use crate::db::errors::DbError;
+use crate::mailer::Email;
+
+/// Creates a reset token and queues the email containing it if an account with the email exists.
+/// Both are done in a single statement, so the work done does not depend on whether the account exists.
+pub async fn create_reset_token(client: &Client, email: &Email, token_hash: &String, valid_seconds: i64) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "
+        WITH token AS (
+            INSERT INTO password_reset_tokens (token_hash, user_id, expires_at)
+            SELECT $4, user_id, CURRENT_TIMESTAMP + $5::BIGINT * INTERVAL '1 second'
+            FROM users WHERE email=$1
+            RETURNING user_id
+        )
+        INSERT INTO email_outbox (recipient, subject, body, expires_at)
+        SELECT $1, $2, $3, CURRENT_TIMESTAMP + $5::BIGINT * INTERVAL '1 second' FROM token",
+        &[&email.recipient, &email.subject, &email.body, token_hash, &valid_seconds])
+        .await
+        .map_err(|err| {
+            debug!("Error while creating password reset token. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+/// Uses the reset token to set a new password. All sessions and other reset tokens of the user are removed.
+/// The email is marked as verified, since the link was delivered to it.
+/// Returns the id of the user, or None if the token does not exist, has expired or has already been used.
+pub async fn reset_password(client: &mut Client, token_hash: &String, new_pwhash: &String) -> Result<Option<uuid::Uuid>, DbError> {
+    let transaction = client.transaction()
+        .await
+        .map_err(|err| {
+            debug!("Failed to start transaction. {}", err);
+            DbError::InternalError
+        })?;
+
+    let row = transaction.query_opt(
+        // language=postgresql
+        "
+        UPDATE password_reset_tokens SET used_at=CURRENT_TIMESTAMP
+        WHERE token_hash=$1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        RETURNING user_id", &[token_hash])
+        .await
+        .map_err(|err| {
+            debug!("Error while using password reset token. {}", err);
+            DbError::InternalError
+        })?;
+
+    let user_id: uuid::Uuid = match row {
+        Some(row) => row.get("user_id"),
+        None => return Ok(None)
+    };
+
+    transaction.execute(
+        // language=postgresql
+        "UPDATE users SET pwhash=$1, email_verified_at=COALESCE(email_verified_at, CURRENT_TIMESTAMP) WHERE user_id=$2",
+        &[new_pwhash, &user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while resetting password. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.execute(
+        // language=postgresql
+        "DELETE FROM sessions WHERE user_id=$1", &[&user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting user sessions. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.execute(
+        // language=postgresql
+        "DELETE FROM password_reset_tokens WHERE user_id=$1 AND used_at IS NULL", &[&user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting password reset tokens. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.commit()
+        .await
+        .map_err(|err| {
+            debug!("Failed to commit password reset. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(Some(user_id))
+}
@@ -1,19 +1,39 @@
-use deadpool_postgres::Client;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Client, Transaction};
 use log::debug;
 use uuid::Uuid;
 
 use crate::db::errors::DbError;
-use crate::db::models::Post;
+use crate::db::reactions;
+use crate::db::models::{Cursor, Post, PostEvent, PostRevision, SearchCursor, SearchResult, ThreadPost, TrendingTag};
+use crate::entities;
+
+/// Limits the posts returned by `get_posts`, `get_posts_before` and `get_posts_after`
+#[derive(Default)]
+pub struct PostFilter {
+    // Only posts by this user and the users they follow
+    pub home_of: Option<Uuid>,
+    // Only posts by this user
+    pub author_id: Option<Uuid>,
+    // Only posts with this hashtag. Replies are included too.
+    pub tag: Option<String>,
+}
 
-pub async fn get_posts(client: &Client, limit: i32, offset: i32) -> Result<Vec<Post>, DbError> {
+/// Gets the newest posts.
+pub async fn get_posts(client: &Client, filter: &PostFilter, viewer_id: Option<&Uuid>, limit: i32, offset: i32) -> Result<Vec<Post>, DbError> {
     let rows = client.query(
         // language=postgresql
         "
-        SELECT post_id, p.user_id, created_at as timestamp, data as text, u.username
-        FROM posts p
-        INNER JOIN users u on u.user_id = p.user_id
-        ORDER BY created_at DESC
-        LIMIT $1::INT OFFSET $2::INT", &[&limit, &offset]
+        SELECT p.*
+        FROM post_view p
+        WHERE (parent_post_id IS NULL OR $5::TEXT IS NOT NULL)
+          AND ($3::uuid IS NULL OR p.user_id=$3 OR p.user_id IN (SELECT followee_id FROM follows WHERE follower_id=$3))
+          AND ($4::uuid IS NULL OR p.user_id=$4)
+          AND ($5::TEXT IS NULL OR EXISTS(SELECT 1 FROM post_hashtags h WHERE h.post_id=p.post_id AND h.tag=$5))
+        ORDER BY p.created_at DESC, p.post_id DESC
+        LIMIT $1::INT OFFSET $2::INT", &[&limit, &offset, &filter.home_of, &filter.author_id, &filter.tag]
     )
         .await
         .map_err(|err| {
@@ -21,14 +41,219 @@ pub async fn get_posts(client: &Client, limit: i32, offset: i32) -> Result<Vec<P
             DbError::InternalError
         })?;
 
-    Ok(rows.into_iter().map(|row| Post::from(&row)).collect())
+    let mut posts: Vec<Post> = rows.into_iter().map(|row| Post::from(&row)).collect();
+    reactions::mark_own_reactions(client, viewer_id, posts.iter_mut()).await?;
+
+    Ok(posts)
 }
 
-pub async fn create_post(client: &Client, user_id: &Uuid, text: &String) -> Result<Uuid, DbError> {
-    let row = client.query_one(
+pub async fn get_posts_before(client: &Client, filter: &PostFilter, viewer_id: Option<&Uuid>, cursor: &Cursor, limit: i32) -> Result<Vec<Post>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT p.*
+        FROM post_view p
+        WHERE (parent_post_id IS NULL OR $6::TEXT IS NOT NULL) AND (p.created_at, p.post_id) < ($1, $2)
+          AND ($4::uuid IS NULL OR p.user_id=$4 OR p.user_id IN (SELECT followee_id FROM follows WHERE follower_id=$4))
+          AND ($5::uuid IS NULL OR p.user_id=$5)
+          AND ($6::TEXT IS NULL OR EXISTS(SELECT 1 FROM post_hashtags h WHERE h.post_id=p.post_id AND h.tag=$6))
+        ORDER BY p.created_at DESC, p.post_id DESC
+        LIMIT $3::INT", &[&cursor.created_at, &cursor.id, &limit, &filter.home_of, &filter.author_id, &filter.tag]
+    )
+        .await
+        .map_err(|err| {
+            debug!("Error while getting posts. {}", err);
+            DbError::InternalError
+        })?;
+
+    let mut posts: Vec<Post> = rows.into_iter().map(|row| Post::from(&row)).collect();
+    reactions::mark_own_reactions(client, viewer_id, posts.iter_mut()).await?;
+
+    Ok(posts)
+}
+
+/// Gets the posts newer than the cursor. Returns the page next to the cursor, newest first.
+pub async fn get_posts_after(client: &Client, filter: &PostFilter, viewer_id: Option<&Uuid>, cursor: &Cursor, limit: i32) -> Result<Vec<Post>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT * FROM (
+            SELECT p.*
+            FROM post_view p
+            WHERE (parent_post_id IS NULL OR $6::TEXT IS NOT NULL) AND (p.created_at, p.post_id) > ($1, $2)
+              AND ($4::uuid IS NULL OR p.user_id=$4 OR p.user_id IN (SELECT followee_id FROM follows WHERE follower_id=$4))
+              AND ($5::uuid IS NULL OR p.user_id=$5)
+              AND ($6::TEXT IS NULL OR EXISTS(SELECT 1 FROM post_hashtags h WHERE h.post_id=p.post_id AND h.tag=$6))
+            ORDER BY p.created_at, p.post_id
+            LIMIT $3::INT
+        ) page
+        ORDER BY created_at DESC, post_id DESC", &[&cursor.created_at, &cursor.id, &limit, &filter.home_of, &filter.author_id, &filter.tag]
+    )
+        .await
+        .map_err(|err| {
+            debug!("Error while getting posts. {}", err);
+            DbError::InternalError
+        })?;
+
+    let mut posts: Vec<Post> = rows.into_iter().map(|row| Post::from(&row)).collect();
+    reactions::mark_own_reactions(client, viewer_id, posts.iter_mut()).await?;
+
+    Ok(posts)
+}
+
+pub struct PostSearch {
+    pub query: String,
+    pub author_id: Option<Uuid>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Finds the posts matching the search, best matches first.
+pub async fn search_posts(client: &Client, search: &PostSearch, viewer_id: Option<&Uuid>, cursor: Option<&SearchCursor>, limit: i32) -> Result<Vec<SearchResult>, DbError> {
+    let rows = client.query(
         // language=postgresql
-        "INSERT INTO posts (user_id, data) VALUES ($1, $2) RETURNING post_id",
-        &[&user_id, &text]
+        "
+        WITH search AS (SELECT websearch_to_tsquery('english', $1) AS query)
+        SELECT p.*,
+               ts_rank(s.search_vector, search.query) AS rank,
+               ts_headline('english', p.text, search.query,
+                   'MaxFragments=2, MaxWords=30, MinWords=10, StartSel=' || chr(2) || ', StopSel=' || chr(3)) AS snippet
+        FROM posts s
+        CROSS JOIN search
+        INNER JOIN post_view p ON p.post_id = s.post_id
+        WHERE s.search_vector @@ search.query
+          AND ($2::uuid IS NULL OR p.user_id=$2)
+          AND ($3::TIMESTAMPTZ IS NULL OR p.created_at >= $3)
+          AND ($4::TIMESTAMPTZ IS NULL OR p.created_at < $4)
+          AND ($5::REAL IS NULL OR (ts_rank(s.search_vector, search.query), p.post_id) < ($5, $6))
+        ORDER BY rank DESC, p.post_id DESC
+        LIMIT $7::INT",
+        &[&search.query, &search.author_id, &search.since, &search.until,
+            &cursor.map(|cursor| cursor.rank), &cursor.map(|cursor| cursor.post_id), &limit])
+        .await
+        .map_err(|err| {
+            debug!("Error while searching posts. {}", err);
+            DbError::InternalError
+        })?;
+
+    let mut results: Vec<SearchResult> = rows.into_iter().map(|row| SearchResult::from(&row)).collect();
+    reactions::mark_own_reactions(client, viewer_id, results.iter_mut().map(|result| &mut result.post)).await?;
+
+    Ok(results)
+}
+
+pub async fn get_post(client: &Client, post_id: &Uuid, viewer_id: Option<&Uuid>) -> Result<Option<Post>, DbError> {
+    let row = client.query_opt(
+        // language=postgresql
+        "SELECT p.* FROM post_view p WHERE post_id=$1", &[post_id]
+    )
+        .await
+        .map_err(|err| {
+            debug!("Error while getting post. {}", err);
+            DbError::InternalError
+        })?;
+
+    let mut post = row.map(|row| Post::from(&row));
+    reactions::mark_own_reactions(client, viewer_id, post.iter_mut()).await?;
+
+    Ok(post)
+}
+
+/// Gets the post and the replies below it, at most `depth` levels deep. `depth` must be at least 1.
+/// `limit` and `offset` page the direct replies of the post, and deeper levels
+/// include at most `limit` replies per post. `reply_count` tells if some were left out.
+pub async fn get_thread(client: &Client, post_id: &Uuid, viewer_id: Option<&Uuid>, depth: i32, limit: i32, offset: i32) -> Result<Option<ThreadPost>, DbError> {
+    let mut post = match get_post(client, post_id, None).await? {
+        Some(post) => post,
+        None => return Ok(None)
+    };
+
+    let rows = client.query(
+        // language=postgresql
+        "
+        WITH RECURSIVE tree AS (
+            (SELECT post_id, 1 AS depth, created_at
+            FROM posts
+            WHERE parent_post_id=$1
+            ORDER BY created_at, post_id
+            LIMIT $3::INT OFFSET $4::INT)
+            UNION ALL
+            SELECT r.post_id, t.depth + 1, r.created_at
+            FROM tree t
+            CROSS JOIN LATERAL (
+                SELECT post_id, created_at
+                FROM posts
+                WHERE parent_post_id=t.post_id
+                ORDER BY created_at, post_id
+                LIMIT $3::INT
+            ) r
+            WHERE t.depth < $2::INT
+        )
+        SELECT p.*
+        FROM tree t
+        INNER JOIN post_view p ON p.post_id = t.post_id
+        ORDER BY p.created_at, p.post_id", &[post_id, &depth, &limit, &offset]
+    )
+        .await
+        .map_err(|err| {
+            debug!("Error while getting thread. {}", err);
+            DbError::InternalError
+        })?;
+
+    let mut posts: Vec<Post> = rows.into_iter().map(|row| Post::from(&row)).collect();
+    reactions::mark_own_reactions(client, viewer_id, std::iter::once(&mut post).chain(posts.iter_mut())).await?;
+
+    // Rows are ordered by time, so replies keep their order within each parent
+    let mut replies: HashMap<Uuid, Vec<Post>> = HashMap::new();
+    for reply in posts {
+        if let Some(parent_id) = reply.parent_post_id {
+            replies.entry(parent_id).or_default().push(reply);
+        }
+    }
+
+    Ok(Some(build_thread(post, &mut replies)))
+}
+
+fn build_thread(post: Post, replies: &mut HashMap<Uuid, Vec<Post>>) -> ThreadPost {
+    let children = replies.remove(&post.post_id).unwrap_or_default();
+
+    ThreadPost {
+        replies: children.into_iter().map(|reply| build_thread(reply, replies)).collect(),
+        post,
+    }
+}
+
+/// Creates the post if the user is not suspended or banned.
+/// A reply is created only if the parent post exists.
+pub async fn create_post(client: &mut Client, user_id: &Uuid, text: &String, parent_post_id: Option<&Uuid>) -> Result<Option<Uuid>, DbError> {
+    let transaction = client.transaction()
+        .await
+        .map_err(|err| {
+            debug!("Failed to start transaction. {}", err);
+            DbError::InternalError
+        })?;
+
+    let row = transaction.query_opt(
+        // language=postgresql
+        "
+        WITH post AS (
+            INSERT INTO posts (user_id, data, parent_post_id, root_post_id)
+            SELECT u.user_id, $2, parent.post_id, COALESCE(parent.root_post_id, parent.post_id)
+            FROM users u
+            LEFT JOIN posts parent ON parent.post_id=$3
+            WHERE u.user_id=$1 AND NOT u.banned AND (u.suspended_until IS NULL OR u.suspended_until <= CURRENT_TIMESTAMP)
+              AND ($3::uuid IS NULL OR parent.post_id IS NOT NULL)
+            RETURNING post_id, user_id, parent_post_id
+        ), reply_notification AS (
+            INSERT INTO notifications (user_id, kind, actor_id, post_id)
+            SELECT parent.user_id, 'reply', post.user_id, post.post_id
+            FROM post
+            INNER JOIN posts parent ON parent.post_id = post.parent_post_id
+            WHERE parent.user_id <> post.user_id
+              AND COALESCE((SELECT replies FROM notification_preferences np WHERE np.user_id=parent.user_id), TRUE)
+        )
+        SELECT post_id FROM post",
+        &[&user_id, &text, &parent_post_id]
     )
         .await
         .map_err(|err| {
@@ -36,7 +261,156 @@ pub async fn create_post(client: &Client, user_id: &Uuid, text: &String) -> Resu
             DbError::InternalError
         })?;
 
-    Ok(row.get("post_id"))
+    let post_id = match row {
+        Some(row) => row.get("post_id"),
+        None => return Ok(None)
+    };
+    save_entities(&transaction, &post_id, text).await?;
+    notify_post_event(&transaction, "created", &post_id).await?;
+
+    transaction.commit()
+        .await
+        .map_err(|err| {
+            debug!("Failed to commit post. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(Some(post_id))
+}
+
+/// Replaces the text of the post and saves the previous text as a revision.
+/// Returns the edit time, or None if the user does not own the post or is suspended or banned.
+pub async fn edit_post(client: &mut Client, user_id: &Uuid, post_id: &Uuid, text: &String) -> Result<Option<DateTime<Utc>>, DbError> {
+    let transaction = client.transaction()
+        .await
+        .map_err(|err| {
+            debug!("Failed to start transaction. {}", err);
+            DbError::InternalError
+        })?;
+
+    // The row is locked, so concurrent edits cannot save the same text as a revision twice
+    let row = transaction.query_opt(
+        // language=postgresql
+        "
+        WITH previous AS (
+            SELECT posts.post_id, posts.data, COALESCE(posts.edited_at, posts.created_at, CURRENT_TIMESTAMP) AS written_at
+            FROM posts
+            INNER JOIN users u USING (user_id)
+            WHERE post_id=$1 AND user_id=$2
+              AND NOT u.banned AND (u.suspended_until IS NULL OR u.suspended_until <= CURRENT_TIMESTAMP)
+            FOR UPDATE OF posts
+        ), revision AS (
+            INSERT INTO post_revisions (post_id, text, created_at)
+            SELECT post_id, data, written_at FROM previous
+        )
+        UPDATE posts p SET data=$3, edited_at=CURRENT_TIMESTAMP
+        FROM previous
+        WHERE p.post_id=previous.post_id
+        RETURNING p.edited_at", &[post_id, user_id, text])
+        .await
+        .map_err(|err| {
+            debug!("Error while editing post. {}", err);
+            DbError::InternalError
+        })?;
+
+    let edited_at = match row {
+        Some(row) => row.get("edited_at"),
+        None => return Ok(None)
+    };
+    save_entities(&transaction, post_id, text).await?;
+
+    transaction.commit()
+        .await
+        .map_err(|err| {
+            debug!("Failed to commit post edit. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(Some(edited_at))
+}
+
+/// Replaces the mentions and hashtags of the post with the ones in the text.
+/// Mentions of handles that do not exist are left out. Newly mentioned users are notified,
+/// so editing a post does not notify the users who were already mentioned.
+async fn save_entities(transaction: &Transaction<'_>, post_id: &Uuid, text: &str) -> Result<(), DbError> {
+    let (mentions, tags) = entities::extract(text);
+
+    transaction.execute(
+        // language=postgresql
+        "
+        DELETE FROM post_mentions m
+        WHERE post_id=$1 AND NOT EXISTS(SELECT 1 FROM users u WHERE u.user_id=m.user_id AND u.handle_key = ANY($2))",
+        &[post_id, &mentions])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting post mentions. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.execute(
+        // language=postgresql
+        "
+        WITH mentioned AS (
+            INSERT INTO post_mentions (post_id, user_id)
+            SELECT $1, user_id FROM users WHERE handle_key = ANY($2)
+            ON CONFLICT DO NOTHING
+            RETURNING post_id, user_id
+        )
+        INSERT INTO notifications (user_id, kind, actor_id, post_id)
+        SELECT m.user_id, 'mention', p.user_id, p.post_id
+        FROM mentioned m
+        INNER JOIN posts p ON p.post_id = m.post_id
+        WHERE m.user_id <> p.user_id
+          AND COALESCE((SELECT mentions FROM notification_preferences np WHERE np.user_id=m.user_id), TRUE)",
+        &[post_id, &mentions])
+        .await
+        .map_err(|err| {
+            debug!("Error while saving post mentions. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.execute(
+        // language=postgresql
+        "DELETE FROM post_hashtags WHERE post_id=$1", &[post_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting post hashtags. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.execute(
+        // language=postgresql
+        "
+        INSERT INTO post_hashtags (post_id, tag, created_at)
+        SELECT p.post_id, tag, p.created_at
+        FROM posts p
+        CROSS JOIN unnest($2::TEXT[]) tag
+        WHERE p.post_id=$1", &[post_id, &tags])
+        .await
+        .map_err(|err| {
+            debug!("Error while saving post hashtags. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+/// Gets the earlier versions of the post, oldest first.
+pub async fn get_revisions(client: &Client, post_id: &Uuid) -> Result<Vec<PostRevision>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT revision_id, text, created_at, replaced_at
+        FROM post_revisions
+        WHERE post_id=$1
+        ORDER BY revision_id", &[post_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting post revisions. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| PostRevision::from(&row)).collect())
 }
 
 pub async fn post_belongs_to_user(client: &Client, user_id: &Uuid, post_id: &Uuid) -> Result<Option<bool>, DbError> {
@@ -68,7 +442,13 @@ pub async fn post_belongs_to_user(client: &Client, user_id: &Uuid, post_id: &Uui
 pub async fn delete_post(client: &Client, user_id: &Uuid, post_id: &Uuid) -> Result<u64, DbError> {
     let result = client.execute(
         // language=postgresql
-        "DELETE FROM posts WHERE user_id=$1 AND post_id=$2", &[user_id, post_id])
+        "
+        WITH deleted AS (
+            DELETE FROM posts WHERE user_id=$1 AND post_id=$2 RETURNING post_id
+        ), event AS (
+            INSERT INTO post_events (kind, post_id) SELECT 'deleted', post_id FROM deleted RETURNING event_id
+        )
+        SELECT pg_notify('post_events', event_id::TEXT) FROM event", &[user_id, post_id])
         .await
         .map_err(|err| {
             debug!("Error while deleting post. {}", err);
@@ -78,15 +458,127 @@ pub async fn delete_post(client: &Client, user_id: &Uuid, post_id: &Uuid) -> Res
     Ok(result)
 }
 
-pub async fn delete_post_admin(client: &Client, post_id: &Uuid) -> Result<u64, DbError> {
-    let result = client.execute(
+/// Deletes the post regardless of its owner. Returns the owner of the deleted post.
+pub async fn delete_post_admin(client: &Client, post_id: &Uuid) -> Result<Option<Uuid>, DbError> {
+    let row = client.query_opt(
         // language=postgresql
-        "DELETE FROM posts WHERE post_id=$1", &[post_id])
+        "
+        WITH deleted AS (
+            DELETE FROM posts WHERE post_id=$1 RETURNING post_id, user_id
+        ), event AS (
+            INSERT INTO post_events (kind, post_id) SELECT 'deleted', post_id FROM deleted RETURNING event_id
+        )
+        SELECT deleted.user_id, pg_notify('post_events', event_id::TEXT) FROM deleted, event", &[post_id])
         .await
         .map_err(|err| {
             debug!("Error while deleting post. {}", err);
             DbError::InternalError
         })?;
 
-    Ok(result)
+    Ok(row.map(|row| row.get("user_id")))
+}
+
+/// Saves the event and notifies the listeners of the `post_events` channel once the transaction commits.
+async fn notify_post_event(transaction: &Transaction<'_>, kind: &str, post_id: &Uuid) -> Result<(), DbError> {
+    transaction.execute(
+        // language=postgresql
+        "
+        WITH event AS (
+            INSERT INTO post_events (kind, post_id) VALUES ($1, $2) RETURNING event_id
+        )
+        SELECT pg_notify('post_events', event_id::TEXT) FROM event", &[&kind, post_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while saving post event. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+/// Gets at most `limit` events newer than `after`, oldest first.
+pub async fn get_post_events(client: &Client, after: i64, limit: i64) -> Result<Vec<PostEvent>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT e.event_id, e.kind, e.post_id AS event_post_id, p.*
+        FROM post_events e
+        LEFT JOIN post_view p ON p.post_id = e.post_id AND e.kind = 'created'
+        WHERE e.event_id > $1
+        ORDER BY e.event_id
+        LIMIT $2", &[&after, &limit])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting post events. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.iter().filter_map(PostEvent::from_row).collect())
+}
+
+/// Id of the newest event, including deleted ones, or 0 if there have been none
+pub async fn get_last_post_event_id(client: &Client) -> Result<i64, DbError> {
+    let row = client.query_one(
+        // language=postgresql
+        "
+        SELECT GREATEST((SELECT MAX(event_id) FROM post_events), (SELECT up_to FROM post_events_pruned), 0) AS event_id", &[])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting the last post event. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.get("event_id"))
+}
+
+/// Newest event that has been deleted. Clients that have not got it cannot be sent all the events they missed.
+pub async fn get_pruned_post_event_id(client: &Client) -> Result<i64, DbError> {
+    let row = client.query_one(
+        // language=postgresql
+        "SELECT up_to FROM post_events_pruned", &[])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting the pruned post events. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.get("up_to"))
+}
+
+pub async fn delete_old_post_events(client: &Client) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "
+        WITH deleted AS (
+            DELETE FROM post_events WHERE created_at < CURRENT_TIMESTAMP - INTERVAL '1 day' RETURNING event_id
+        )
+        UPDATE post_events_pruned SET up_to=GREATEST(up_to, (SELECT MAX(event_id) FROM deleted))", &[])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting old post events. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+/// Gets the hashtags used by the most users in the last `hours` hours.
+pub async fn get_trending_tags(client: &Client, hours: i32, limit: i32) -> Result<Vec<TrendingTag>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT h.tag, COUNT(*) AS post_count, COUNT(DISTINCT p.user_id) AS user_count
+        FROM post_hashtags h
+        INNER JOIN posts p ON p.post_id = h.post_id
+        WHERE h.created_at > CURRENT_TIMESTAMP - make_interval(hours => $1)
+        GROUP BY h.tag
+        ORDER BY user_count DESC, post_count DESC, h.tag
+        LIMIT $2::INT", &[&hours, &limit])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting trending hashtags. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| TrendingTag::from(&row)).collect())
 }
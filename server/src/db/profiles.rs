@@ -0,0 +1,65 @@
+use deadpool_postgres::Client;
+use log::debug;
+use uuid::Uuid;
+
+use crate::db::errors::DbError;
+use crate::handles;
+use crate::db::models::Profile;
+
+pub async fn get_profile(client: &Client, user_id: &Uuid) -> Result<Option<Profile>, DbError> {
+    let row = client.query_opt(
+        // language=postgresql
+        "
+        SELECT user_id, username, handle, display_name, bio, avatar_url, created_at AS joined_at,
+               (SELECT COUNT(*) FROM posts p WHERE p.user_id=users.user_id) AS post_count
+        FROM users WHERE user_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting profile. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.map(|row| Profile::from(&row)))
+}
+
+/// Finds the profile without regard to case or confusable characters in the handle.
+pub async fn get_profile_by_handle(client: &Client, handle: &str) -> Result<Option<Profile>, DbError> {
+    let row = client.query_opt(
+        // language=postgresql
+        "
+        SELECT user_id, username, handle, display_name, bio, avatar_url, created_at AS joined_at,
+               (SELECT COUNT(*) FROM posts p WHERE p.user_id=users.user_id) AS post_count
+        FROM users WHERE handle_key=$1", &[&handles::key(handle)])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting profile. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.map(|row| Profile::from(&row)))
+}
+
+/// Fields that are None are not changed. Empty strings clear the field.
+pub struct ProfileUpdate {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+pub async fn update_profile(client: &Client, user_id: &Uuid, update: &ProfileUpdate) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "
+        UPDATE users SET
+            display_name=CASE WHEN $2::TEXT IS NULL THEN display_name ELSE NULLIF($2, '') END,
+            bio=CASE WHEN $3::TEXT IS NULL THEN bio ELSE NULLIF($3, '') END,
+            avatar_url=CASE WHEN $4::TEXT IS NULL THEN avatar_url ELSE NULLIF($4, '') END
+        WHERE user_id=$1", &[user_id, &update.display_name, &update.bio, &update.avatar_url])
+        .await
+        .map_err(|err| {
+            debug!("Error while updating profile. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
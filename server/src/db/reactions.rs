@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+
+use deadpool_postgres::Client;
+use log::debug;
+use uuid::Uuid;
+
+use crate::db::errors::DbError;
+use crate::db::models::{Cursor, Post, ReactionUser};
+
+/// Adds the reaction to the post. Reacting again with the same reaction does nothing.
+/// Returns None if the post does not exist and false if the user is suspended or banned.
+pub async fn add_reaction(client: &Client, user_id: &Uuid, post_id: &Uuid, reaction: &str) -> Result<Option<bool>, DbError> {
+    let row = client.query_opt(
+        // language=postgresql
+        "
+        WITH allowed AS (
+            SELECT user_id FROM users
+            WHERE user_id=$2 AND NOT banned AND (suspended_until IS NULL OR suspended_until <= CURRENT_TIMESTAMP)
+        ), added AS (
+            INSERT INTO reactions (post_id, user_id, reaction)
+            SELECT p.post_id, a.user_id, $3 FROM posts p CROSS JOIN allowed a WHERE p.post_id=$1
+            ON CONFLICT DO NOTHING
+        )
+        SELECT EXISTS(SELECT 1 FROM allowed) AS allowed FROM posts WHERE post_id=$1", &[post_id, user_id, &reaction])
+        .await
+        .map_err(|err| {
+            debug!("Error while adding reaction. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.map(|row| row.get("allowed")))
+}
+
+pub async fn remove_reaction(client: &Client, user_id: &Uuid, post_id: &Uuid, reaction: &str) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "DELETE FROM reactions WHERE post_id=$1 AND user_id=$2 AND reaction=$3", &[post_id, user_id, &reaction])
+        .await
+        .map_err(|err| {
+            debug!("Error while removing reaction. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+/// Gets the users who reacted to the post, most recent first. All reactions are included if `reaction` is None.
+pub async fn get_reactions(client: &Client, post_id: &Uuid, reaction: Option<&str>, before: Option<&Cursor>, limit: i32) -> Result<Vec<ReactionUser>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT u.user_id, u.username, u.handle, r.reaction, r.created_at AS reacted_at
+        FROM reactions r
+        INNER JOIN users u ON u.user_id = r.user_id
+        WHERE r.post_id=$1
+          AND ($2::TEXT IS NULL OR r.reaction=$2)
+          AND ($3::TIMESTAMPTZ IS NULL OR (r.created_at, r.user_id) < ($3, $4))
+        ORDER BY r.created_at DESC, r.user_id DESC
+        LIMIT $5::INT",
+        &[post_id, &reaction, &before.map(|cursor| cursor.created_at), &before.map(|cursor| cursor.id), &limit])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting reactions. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| ReactionUser::from(&row)).collect())
+}
+
+/// Marks the reactions that the viewer added to the posts. The reactions of all posts are read with one query.
+pub async fn mark_own_reactions<'a>(client: &Client, viewer_id: Option<&Uuid>, posts: impl IntoIterator<Item=&'a mut Post>) -> Result<(), DbError> {
+    let viewer_id = match viewer_id {
+        Some(viewer_id) => viewer_id,
+        None => return Ok(())
+    };
+    let mut posts: Vec<&mut Post> = posts.into_iter().filter(|post| !post.reactions.is_empty()).collect();
+    if posts.is_empty() {
+        return Ok(());
+    }
+
+    let post_ids: Vec<Uuid> = posts.iter().map(|post| post.post_id).collect();
+    let rows = client.query(
+        // language=postgresql
+        "SELECT post_id, reaction FROM reactions WHERE user_id=$1 AND post_id = ANY($2)", &[viewer_id, &post_ids])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting own reactions. {}", err);
+            DbError::InternalError
+        })?;
+
+    let own: HashSet<(Uuid, String)> = rows.into_iter().map(|row| (row.get("post_id"), row.get("reaction"))).collect();
+    for post in posts.iter_mut() {
+        let post_id = post.post_id;
+        for reaction in post.reactions.iter_mut() {
+            reaction.reacted_by_me = own.contains(&(post_id, reaction.reaction.clone()));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use deadpool_postgres::Client;
+use log::debug;
+use uuid::Uuid;
+
+use crate::db::errors::DbError;
+use crate::db::models::Restriction;
+
+/// Gets the suspension or ban of the user if one is in effect.
+pub async fn get_restriction(client: &Client, user_id: &Uuid) -> Result<Option<Restriction>, DbError> {
+    let row = client.query_opt(
+        // language=postgresql
+        "
+        SELECT banned, suspended_until, restriction_reason FROM users
+        WHERE user_id=$1 AND (banned OR suspended_until > CURRENT_TIMESTAMP)", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting user restriction. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.map(|row| Restriction::from(&row)))
+}
+
+/// Suspends the user for the given time or bans them if no time is given.
+/// All sessions of the user are revoked in the same transaction. Returns false if the user does not exist.
+pub async fn restrict_user(client: &mut Client, user_id: &Uuid, restricted_by: &Uuid, reason: &String, suspend_hours: Option<i32>) -> Result<bool, DbError> {
+    let transaction = client.transaction()
+        .await
+        .map_err(|err| {
+            debug!("Failed to start transaction. {}", err);
+            DbError::InternalError
+        })?;
+
+    let result = transaction.execute(
+        // language=postgresql
+        "
+        UPDATE users SET
+            banned=$4::INT IS NULL,
+            suspended_until=CURRENT_TIMESTAMP + make_interval(hours => $4),
+            restriction_reason=$3,
+            restricted_by=$2,
+            restricted_at=CURRENT_TIMESTAMP
+        WHERE user_id=$1",
+        &[user_id, restricted_by, reason, &suspend_hours])
+        .await
+        .map_err(|err| {
+            debug!("Error while restricting user. {}", err);
+            DbError::InternalError
+        })?;
+
+    if result == 0 {
+        return Ok(false)
+    }
+
+    transaction.execute(
+        // language=postgresql
+        "DELETE FROM sessions WHERE user_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting user sessions. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.commit()
+        .await
+        .map_err(|err| {
+            debug!("Failed to commit user restriction. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(true)
+}
+
+/// Lifts a suspension or a ban. The reason and the acting user are kept for reference.
+pub async fn lift_restriction(client: &Client, user_id: &Uuid) -> Result<bool, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "UPDATE users SET banned=FALSE, suspended_until=NULL WHERE user_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while lifting user restriction. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result == 1)
+}
@@ -0,0 +1,54 @@
+use deadpool_postgres::Client;
+use log::debug;
+use uuid::Uuid;
+
+use crate::db::errors::DbError;
+use crate::db::models::{Permission, Role};
+
+pub async fn has_permission(client: &Client, user_id: &Uuid, permission: Permission) -> Result<bool, DbError> {
+    let row = client.query_one(
+        // language=postgresql
+        "
+        SELECT EXISTS(
+            SELECT 1 FROM users u
+            INNER JOIN role_permissions rp ON rp.role=u.role
+            WHERE u.user_id=$1 AND rp.permission=$2
+        ) AS allowed", &[user_id, &permission.as_str()])
+        .await
+        .map_err(|err| {
+            debug!("Error while checking permission. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.get("allowed"))
+}
+
+pub async fn get_roles(client: &Client) -> Result<Vec<Role>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT r.role, ARRAY(SELECT permission FROM role_permissions rp WHERE rp.role=r.role ORDER BY permission) AS permissions
+        FROM roles r
+        ORDER BY r.role", &[])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting roles. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| Role::from(&row)).collect())
+}
+
+/// Sets the role of the user. Returns false if the user or the role does not exist.
+pub async fn set_user_role(client: &Client, user_id: &Uuid, role: &String) -> Result<bool, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "UPDATE users SET role=r.role FROM roles r WHERE users.user_id=$1 AND r.role=$2", &[user_id, role])
+        .await
+        .map_err(|err| {
+            debug!("Error while setting user role. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result == 1)
+}
@@ -6,21 +6,22 @@ use actix_web::cookie::time::Duration;
 use deadpool_postgres::{Client, Manager, ManagerConfig, Pool, PoolError, RecyclingMethod};
 use log::debug;
 use rand::{distributions::Alphanumeric, Rng as _, rngs::OsRng};
-use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 use tokio_postgres::{Config, NoTls};
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
-pub fn clear_old_sessions(config: Config) -> (tokio::task::JoinHandle<Result<(), PoolError>>, CancellationToken) {
+/// Periodically deletes expired sessions and login attempts that no longer affect logging in.
+pub fn clear_old_sessions(config: Config, login_reset_after_seconds: i64) -> (tokio::task::JoinHandle<Result<(), PoolError>>, CancellationToken) {
     let cancel_token = CancellationToken::new();
 
     (
-        tokio::spawn(session_clear_job(config, cancel_token.clone())),
+        tokio::spawn(session_clear_job(config, login_reset_after_seconds, cancel_token.clone())),
         cancel_token
     )
 }
 
-async fn session_clear_job(config: Config, cancel_token: CancellationToken) -> Result<(), PoolError> {
+async fn session_clear_job(config: Config, login_reset_after_seconds: i64, cancel_token: CancellationToken) -> Result<(), PoolError> {
     let mgr_config = ManagerConfig {
         recycling_method: RecyclingMethod::Fast
     };
@@ -31,6 +32,9 @@ async fn session_clear_job(config: Config, cancel_token: CancellationToken) -> R
         let client = pool.get().await?;
         // language=sql
         client.execute("DELETE FROM sessions WHERE expires_at < CURRENT_TIMESTAMP", &[]).await?;
+        if let Err(err) = crate::db::login_attempts::delete_expired(&client, login_reset_after_seconds).await {
+            debug!("Failed to delete expired login attempts. {}", err);
+        }
 
         tokio::select! {
             _ = tokio::time::sleep(std::time::Duration::from_secs(60 * 60 * 2)) => {
@@ -53,9 +57,16 @@ pub struct PostgresSessionStore {
 
 type SessionState = HashMap<String, String>;
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct SessionData {
-    csrf: String
+/// Reads a value from the session state. Values are stored in the state as json strings.
+fn get_state_value<T: DeserializeOwned>(session_state: &SessionState, key: &str) -> Result<Option<T>, anyhow::Error> {
+    session_state.get(key)
+        .map(|v| serde_json::from_str::<T>(v))
+        .transpose()
+        .map_err(|err| {
+            debug!("Failed to parse session value {}. {}", key, err);
+            err
+        })
+        .map_err(anyhow::Error::new)
 }
 
 /// https://docs.rs/actix-session/latest/src/actix_session/storage/utils.rs.html
@@ -97,12 +108,18 @@ impl SessionStore for PostgresSessionStore {
 
         let session_id = session_key.as_ref();
 
+        // Loading happens on every request with a session cookie, so it is used to track when the session was last used.
+        // Sessions of suspended or banned users are not loaded, so they are not logged in even if a session was left over.
         let row = client.query_opt(
             // language=postgresql
             "
-            SELECT data
-            FROM sessions
-            WHERE expires_at > CURRENT_TIMESTAMP AND session_id=$1", &[&session_id.to_string()]
+            UPDATE sessions s
+            SET last_seen_at=CURRENT_TIMESTAMP
+            WHERE expires_at > CURRENT_TIMESTAMP AND session_id=$1 AND NOT EXISTS(
+                SELECT 1 FROM users u
+                WHERE u.user_id=s.user_id AND (u.banned OR u.suspended_until > CURRENT_TIMESTAMP)
+            )
+            RETURNING data", &[&session_id.to_string()]
         )
             .await
             .map_err(|err| {
@@ -143,28 +160,21 @@ impl SessionStore for PostgresSessionStore {
             .map_err(Into::into)
             .map_err(SaveError::Serialization)?;
 
-        // The value is deserialized to json on insert
-        let user_id: Option<Uuid> = match session_state.get("user_id")
-            .map(|v| serde_json::from_str::<String>(v).unwrap())
-            .map(|v|
-                Uuid::parse_str(v.as_str())
-                    .map_err(|err| {
-                        debug!("Failed to parse session user id as uuid. {}", err);
-                        err
-                    })
-                    .map_err(anyhow::Error::new)
-                    .map_err(SaveError::Serialization))
-        {
-            Some(val) => Some(val?),
-            None => None
-        };
+        let user_id: Option<Uuid> = get_state_value(&session_state, "user_id")
+            .map_err(SaveError::Serialization)?;
+        let public_id: Option<Uuid> = get_state_value(&session_state, "public_id")
+            .map_err(SaveError::Serialization)?;
+        let ip: Option<String> = get_state_value(&session_state, "ip")
+            .map_err(SaveError::Serialization)?;
+        let user_agent: Option<String> = get_state_value(&session_state, "user_agent")
+            .map_err(SaveError::Serialization)?;
 
         match client.execute(
             // language=sql
             "
-            INSERT INTO sessions (session_id, expires_at, data, user_id)
-            VALUES ($1, CURRENT_TIMESTAMP + $2::BIGINT * INTERVAL '1 second', $3, $4)"
-        , &[&session_key.as_ref().to_string(), &ttl.whole_seconds(), &data, &user_id])
+            INSERT INTO sessions (session_id, expires_at, data, user_id, public_id, ip, user_agent)
+            VALUES ($1, CURRENT_TIMESTAMP + $2::BIGINT * INTERVAL '1 second', $3, $4, COALESCE($5, uuid_generate_v4()), $6, $7)"
+        , &[&session_key.as_ref().to_string(), &ttl.whole_seconds(), &data, &user_id, &public_id, &ip, &user_agent])
             .await
             .map_err(|err| {
                 debug!("Failed to save session. {}", err);
@@ -195,7 +205,7 @@ impl SessionStore for PostgresSessionStore {
             // language=sql
             "
             UPDATE sessions
-            SET data=$1, expires_at=CURRENT_TIMESTAMP + $2::BIGINT * INTERVAL '1 second'
+            SET data=$1, expires_at=CURRENT_TIMESTAMP + $2::BIGINT * INTERVAL '1 second', last_seen_at=CURRENT_TIMESTAMP
             WHERE session_id=$3",
             &[&data, &ttl.whole_seconds(), &session_key.as_ref().to_string()]
         )
@@ -229,7 +239,7 @@ impl SessionStore for PostgresSessionStore {
             // language=sql
             "
             UPDATE sessions
-            SET expires_at=CURRENT_TIMESTAMP + $1::BIGINT * INTERVAL '1 second'
+            SET expires_at=CURRENT_TIMESTAMP + $1::BIGINT * INTERVAL '1 second', last_seen_at=CURRENT_TIMESTAMP
             WHERE session_id=$2",
             &[&ttl.whole_seconds(), &session_key.as_ref().to_string()]
         )
@@ -0,0 +1,70 @@
+use deadpool_postgres::Client;
+use log::debug;
+use uuid::Uuid;
+
+use crate::db::errors::DbError;
+use crate::db::models::UserSession;
+
+pub async fn get_user_sessions(client: &Client, user_id: &Uuid, current_id: &Option<Uuid>) -> Result<Vec<UserSession>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT public_id, created_at, last_seen_at, ip, user_agent, public_id = $2 IS TRUE as current
+        FROM sessions
+        WHERE user_id=$1 AND expires_at > CURRENT_TIMESTAMP
+        ORDER BY last_seen_at DESC", &[user_id, current_id]
+    )
+        .await
+        .map_err(|err| {
+            debug!("Error while getting user sessions. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| UserSession::from(&row)).collect())
+}
+
+pub async fn delete_user_session(client: &Client, user_id: &Uuid, public_id: &Uuid) -> Result<u64, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "DELETE FROM sessions WHERE user_id=$1 AND public_id=$2", &[user_id, public_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting session. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result)
+}
+
+pub async fn delete_other_user_sessions(client: &Client, user_id: &Uuid, current_id: &Uuid) -> Result<u64, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "DELETE FROM sessions WHERE user_id=$1 AND public_id<>$2", &[user_id, current_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting sessions. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result)
+}
+
+/// Checks that the session has not been logged out or expired, and that the user is not suspended or banned.
+pub async fn is_session_active(client: &Client, user_id: &Uuid, public_id: &Uuid) -> Result<bool, DbError> {
+    let row = client.query_one(
+        // language=postgresql
+        "
+        SELECT EXISTS(
+            SELECT 1 FROM sessions s
+            INNER JOIN users u ON u.user_id = s.user_id
+            WHERE s.user_id=$1 AND s.public_id=$2 AND s.expires_at > CURRENT_TIMESTAMP
+              AND NOT u.banned AND (u.suspended_until IS NULL OR u.suspended_until <= CURRENT_TIMESTAMP)
+        ) AS active", &[user_id, public_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while checking session. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.get("active"))
+}
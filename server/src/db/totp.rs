@@ -0,0 +1,161 @@
+use deadpool_postgres::Client;
+use log::debug;
+use uuid::Uuid;
+
+use crate::db::errors::DbError;
+use crate::db::models::TotpState;
+
+pub async fn get_totp_state(client: &Client, user_id: &Uuid) -> Result<Option<TotpState>, DbError> {
+    let row = client.query_opt(
+        // language=postgresql
+        "SELECT email, totp_secret, totp_enabled FROM users WHERE user_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting totp state. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.map(|row| TotpState::from(&row)))
+}
+
+/// Saves a new secret for the user that is waiting for confirmation.
+/// Returns false if two-factor authentication is already enabled.
+pub async fn set_pending_secret(client: &Client, user_id: &Uuid, secret: &String) -> Result<bool, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "UPDATE users SET totp_secret=$2, totp_last_step=NULL WHERE user_id=$1 AND NOT totp_enabled",
+        &[user_id, secret])
+        .await
+        .map_err(|err| {
+            debug!("Error while setting totp secret. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result == 1)
+}
+
+/// Marks the time step of a code as used. Returns false if the step or a later one has already been used.
+pub async fn use_totp_step(client: &Client, user_id: &Uuid, step: i64) -> Result<bool, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "
+        UPDATE users SET totp_last_step=$2
+        WHERE user_id=$1 AND (totp_last_step IS NULL OR totp_last_step < $2)",
+        &[user_id, &step])
+        .await
+        .map_err(|err| {
+            debug!("Error while using totp step. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result == 1)
+}
+
+/// Enables two-factor authentication and replaces the recovery codes of the user.
+pub async fn enable_totp(client: &mut Client, user_id: &Uuid, step: i64, recovery_code_hashes: &[String]) -> Result<bool, DbError> {
+    let transaction = client.transaction()
+        .await
+        .map_err(|err| {
+            debug!("Failed to start transaction. {}", err);
+            DbError::InternalError
+        })?;
+
+    let result = transaction.execute(
+        // language=postgresql
+        "
+        UPDATE users SET totp_enabled=TRUE, totp_last_step=$2
+        WHERE user_id=$1 AND totp_secret IS NOT NULL AND NOT totp_enabled
+            AND (totp_last_step IS NULL OR totp_last_step < $2)",
+        &[user_id, &step])
+        .await
+        .map_err(|err| {
+            debug!("Error while enabling totp. {}", err);
+            DbError::InternalError
+        })?;
+
+    if result == 0 {
+        return Ok(false)
+    }
+
+    transaction.execute(
+        // language=postgresql
+        "DELETE FROM recovery_codes WHERE user_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting recovery codes. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.execute(
+        // language=postgresql
+        "INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, unnest($2::TEXT[])",
+        &[user_id, &recovery_code_hashes])
+        .await
+        .map_err(|err| {
+            debug!("Error while inserting recovery codes. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.commit()
+        .await
+        .map_err(|err| {
+            debug!("Failed to commit totp enable. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(true)
+}
+
+pub async fn disable_totp(client: &mut Client, user_id: &Uuid) -> Result<(), DbError> {
+    let transaction = client.transaction()
+        .await
+        .map_err(|err| {
+            debug!("Failed to start transaction. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.execute(
+        // language=postgresql
+        "UPDATE users SET totp_secret=NULL, totp_enabled=FALSE, totp_last_step=NULL WHERE user_id=$1",
+        &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while disabling totp. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.execute(
+        // language=postgresql
+        "DELETE FROM recovery_codes WHERE user_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting recovery codes. {}", err);
+            DbError::InternalError
+        })?;
+
+    transaction.commit()
+        .await
+        .map_err(|err| {
+            debug!("Failed to commit totp disable. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+/// Marks the recovery code as used. Returns false if the code does not exist or has already been used.
+pub async fn use_recovery_code(client: &Client, user_id: &Uuid, code_hash: &String) -> Result<bool, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "
+        UPDATE recovery_codes SET used_at=CURRENT_TIMESTAMP
+        WHERE user_id=$1 AND code_hash=$2 AND used_at IS NULL",
+        &[user_id, code_hash])
+        .await
+        .map_err(|err| {
+            debug!("Error while using recovery code. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result == 1)
+}
@@ -9,7 +9,11 @@ use crate::db::models::User;
 pub async fn get_user(client: &Client, user_id: &Uuid) -> Result<Option<User>, DbError> {
     let row = client.query_opt(
         // language=postgresql
-        "SELECT user_id, username, email, admin FROM users WHERE user_id=$1", &[&user_id])
+        "
+        SELECT user_id, username, handle, email, role, totp_enabled, email_verified_at IS NOT NULL AS email_verified,
+               ARRAY(SELECT permission FROM role_permissions rp WHERE rp.role=users.role) AS permissions,
+               EXISTS(SELECT 1 FROM webauthn_credentials c WHERE c.user_id=users.user_id) AS has_passkeys
+        FROM users WHERE user_id=$1", &[&user_id])
         .await
         .map_err(|err| {
             debug!("Error while gettimg user. {}", err);
@@ -22,27 +26,65 @@ pub async fn get_user(client: &Client, user_id: &Uuid) -> Result<Option<User>, D
     }
 }
 
-pub async fn authenticate(client: &Client, email: &String, password: &String) -> Result<Option<User>, DbError> {
+/// Gets the user and the password hash of the user with the given email.
+pub async fn get_user_by_email(client: &Client, email: &String) -> Result<Option<(User, String)>, DbError> {
     let row = client.query_opt(
         // language=postgresql
-        "SELECT user_id, username, email, admin FROM users WHERE email=$1 AND pwhash=crypt($2, pwhash)", &[email, password])
+        "
+        SELECT user_id, username, handle, email, role, totp_enabled, email_verified_at IS NOT NULL AS email_verified,
+               ARRAY(SELECT permission FROM role_permissions rp WHERE rp.role=users.role) AS permissions,
+               EXISTS(SELECT 1 FROM webauthn_credentials c WHERE c.user_id=users.user_id) AS has_passkeys, pwhash
+        FROM users WHERE email=$1", &[email])
         .await
         .map_err(|err| {
-            debug!("Error while authenticating user. {}", err);
+            debug!("Error while getting user by email. {}", err);
             DbError::InternalError
         })?;
 
-    match row {
-        Some(row) => Ok(Some(User::from(&row))),
-        None => Ok(None)
-    }
+    Ok(row.map(|row| (User::from(&row), row.get("pwhash"))))
 }
 
-pub async fn change_password(client: &Client, user_id: &Uuid, password: &String, new_password: &String) -> Result<bool, DbError> {
+pub async fn get_password_hash(client: &Client, user_id: &Uuid) -> Result<Option<String>, DbError> {
+    let row = client.query_opt(
+        // language=postgresql
+        "SELECT pwhash FROM users WHERE user_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting password hash. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.map(|row| row.get("pwhash")))
+}
+
+/// Replaces the password hash only if it has not been changed after `old_pwhash` was read.
+pub async fn update_password_hash(client: &Client, user_id: &Uuid, old_pwhash: &String, new_pwhash: &String) -> Result<bool, DbError> {
     let result = client.execute(
         // language=postgresql
-        "UPDATE users SET pwhash=crypt($1, gen_salt('bf')) WHERE user_id=$2 AND pwhash=crypt($3, pwhash)",
-        &[&new_password, &user_id, &password]
+        "UPDATE users SET pwhash=$3 WHERE user_id=$1 AND pwhash=$2", &[user_id, old_pwhash, new_pwhash])
+        .await
+        .map_err(|err| {
+            debug!("Error while updating password hash. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result == 1)
+}
+
+/// Changes the password hash of the user if the current hash is still `old_pwhash`.
+/// Unless `keep_sessions` is set, all sessions of the user are deleted in the same transaction.
+pub async fn change_password(client: &mut Client, user_id: &Uuid, old_pwhash: &String, new_pwhash: &String, keep_sessions: bool) -> Result<bool, DbError> {
+    let transaction = client.transaction()
+        .await
+        .map_err(|err| {
+            debug!("Failed to start transaction. {}", err);
+            DbError::InternalError
+        })?;
+
+    let result = transaction.execute(
+        // language=postgresql
+        "UPDATE users SET pwhash=$1 WHERE user_id=$2 AND pwhash=$3",
+        &[&new_pwhash, &user_id, &old_pwhash]
     )
         .await
         .map_err(|err| {
@@ -51,21 +93,43 @@ pub async fn change_password(client: &Client, user_id: &Uuid, password: &String,
         })?;
 
     if result == 0 {
-        Ok(false)
-    } else {
-        Ok(true)
+        return Ok(false)
     }
+
+    if !keep_sessions {
+        transaction.execute(
+            // language=postgresql
+            "DELETE FROM sessions WHERE user_id=$1", &[&user_id])
+            .await
+            .map_err(|err| {
+                debug!("Error while deleting user sessions. {}", err);
+                DbError::InternalError
+            })?;
+    }
+
+    transaction.commit()
+        .await
+        .map_err(|err| {
+            debug!("Failed to commit password change. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(true)
 }
 
-pub async fn create_account(client: &Client, username: &String, email: &String, password: &String) -> Result<Uuid, DbError> {
+/// Fails with `DuplicateHandle` if the handle is taken and with `DuplicateKey` if the email is taken.
+pub async fn create_account(client: &Client, username: &String, handle: &String, email: &String, pwhash: &String) -> Result<Uuid, DbError> {
     let row = client.query_one(
         // language=postgresql
-        "INSERT INTO users (username, email, pwhash) VALUES ($1, $2, crypt($3, gen_salt('bf'))) RETURNING user_id",
-        &[&username, &email, &password]
+        "INSERT INTO users (username, handle, email, pwhash) VALUES ($1, $2, $3, $4) RETURNING user_id",
+        &[&username, &handle, &email, &pwhash]
     )
         .await
         .map_err(|err| {
             if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                if err.as_db_error().and_then(|err| err.constraint()) == Some("users_handle_key_unique") {
+                    return DbError::DuplicateHandle
+                }
                 return DbError::DuplicateKey
             }
 
@@ -76,10 +140,11 @@ pub async fn create_account(client: &Client, username: &String, email: &String,
     Ok(row.get("user_id"))
 }
 
-pub async fn delete_account(client: &Client, user_id: &Uuid, password: &String) -> Result<bool, DbError> {
+/// Deletes the account if the password hash is still `pwhash`.
+pub async fn delete_account(client: &Client, user_id: &Uuid, pwhash: &String) -> Result<bool, DbError> {
     let result = client.execute(
         // language=postgresql
-        "DELETE FROM users WHERE user_id=$1 AND pwhash=crypt($2, pwhash)", &[&user_id, &password])
+        "DELETE FROM users WHERE user_id=$1 AND pwhash=$2", &[&user_id, &pwhash])
         .await
         .map_err(|err| {
             debug!("Error while deleting account. {}", err);
@@ -92,3 +157,18 @@ pub async fn delete_account(client: &Client, user_id: &Uuid, password: &String)
         Ok(true)
     }
 }
+
+/// Marks the email as verified if it is still the email of the user.
+pub async fn verify_email(client: &Client, user_id: &Uuid, email: &String) -> Result<bool, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "UPDATE users SET email_verified_at=CURRENT_TIMESTAMP WHERE user_id=$1 AND email=$2 AND email_verified_at IS NULL",
+        &[user_id, email])
+        .await
+        .map_err(|err| {
+            debug!("Error while verifying email. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result == 1)
+}
@@ -0,0 +1,107 @@
+use deadpool_postgres::Client;
+use log::debug;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::Json;
+use uuid::Uuid;
+use webauthn_rs::prelude::Passkey;
+
+use crate::db::errors::DbError;
+use crate::db::models::WebauthnCredential;
+
+pub async fn get_passkeys(client: &Client, user_id: &Uuid) -> Result<Vec<Passkey>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "SELECT passkey FROM webauthn_credentials WHERE user_id=$1", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting passkeys. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| row.get::<&str, Json<Passkey>>("passkey").0).collect())
+}
+
+pub async fn get_passkey(client: &Client, user_id: &Uuid, credential_id: &[u8]) -> Result<Option<(Passkey, u32)>, DbError> {
+    let row = client.query_opt(
+        // language=postgresql
+        "SELECT passkey, counter FROM webauthn_credentials WHERE user_id=$1 AND credential_id=$2",
+        &[user_id, &credential_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting passkey. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(row.map(|row| (
+        row.get::<&str, Json<Passkey>>("passkey").0,
+        row.get::<&str, i64>("counter") as u32
+    )))
+}
+
+pub async fn get_credentials(client: &Client, user_id: &Uuid) -> Result<Vec<WebauthnCredential>, DbError> {
+    let rows = client.query(
+        // language=postgresql
+        "
+        SELECT credential_id, name, created_at, last_used_at
+        FROM webauthn_credentials
+        WHERE user_id=$1
+        ORDER BY created_at", &[user_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while getting webauthn credentials. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(rows.into_iter().map(|row| WebauthnCredential::from(&row)).collect())
+}
+
+pub async fn create_credential(client: &Client, user_id: &Uuid, name: &String, passkey: &Passkey) -> Result<(), DbError> {
+    client.execute(
+        // language=postgresql
+        "
+        INSERT INTO webauthn_credentials (credential_id, user_id, name, passkey)
+        VALUES ($1, $2, $3, $4)",
+        &[&passkey.cred_id().as_slice(), user_id, name, &Json(passkey)])
+        .await
+        .map_err(|err| {
+            if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                return DbError::DuplicateKey
+            }
+
+            debug!("Error while creating webauthn credential. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(())
+}
+
+/// Saves the updated passkey after a successful assertion. The update only happens if the counter
+/// is still lower than the new one, so two concurrent assertions with the same counter cannot both succeed.
+pub async fn update_credential(client: &Client, user_id: &Uuid, passkey: &Passkey, counter: u32) -> Result<bool, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "
+        UPDATE webauthn_credentials SET passkey=$3, counter=$4, last_used_at=CURRENT_TIMESTAMP
+        WHERE user_id=$1 AND credential_id=$2 AND (counter < $4 OR $4 = 0)",
+        &[user_id, &passkey.cred_id().as_slice(), &Json(passkey), &(counter as i64)])
+        .await
+        .map_err(|err| {
+            debug!("Error while updating webauthn credential. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result == 1)
+}
+
+pub async fn delete_credential(client: &Client, user_id: &Uuid, credential_id: &[u8]) -> Result<u64, DbError> {
+    let result = client.execute(
+        // language=postgresql
+        "DELETE FROM webauthn_credentials WHERE user_id=$1 AND credential_id=$2", &[user_id, &credential_id])
+        .await
+        .map_err(|err| {
+            debug!("Error while deleting webauthn credential. {}", err);
+            DbError::InternalError
+        })?;
+
+    Ok(result)
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::handles;
+
+const MAX_TAG_LENGTH: usize = 64;
+
+/// A mention or a hashtag in the text of a post.
+/// `start` and `end` count UTF-16 code units, like string indexes in JavaScript.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Entity {
+    #[serde(rename_all = "camelCase")]
+    Mention { start: usize, end: usize, handle: String, user_id: Uuid },
+    Hashtag { start: usize, end: usize, tag: String },
+}
+
+/// Mentions and hashtags found in a text, before the mentioned users are looked up
+#[derive(Debug, PartialEq)]
+enum Token {
+    Mention(String),
+    Hashtag(String),
+}
+
+fn is_handle_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Finds the mentions and hashtags with their UTF-16 ranges. A mention or a hashtag must not directly follow
+/// a letter or a digit, so emails and URL fragments are not matched.
+fn tokenize(text: &str) -> Vec<(usize, usize, Token)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let after_word = i > 0 && is_tag_char(chars[i - 1]);
+
+        if (c == '@' || c == '#') && !after_word {
+            let is_part: fn(char) -> bool = if c == '@' { is_handle_char } else { is_tag_char };
+            let length = chars[i + 1..].iter().take_while(|c| is_part(**c)).count();
+            let word: String = chars[i + 1..i + 1 + length].iter().collect();
+
+            let token = if c == '@' {
+                Some(Token::Mention(word.clone())).filter(|_| handles::validate(&word).is_ok())
+            } else {
+                Some(Token::Hashtag(word.to_lowercase()))
+                    .filter(|_| length <= MAX_TAG_LENGTH && word.chars().any(|c| !c.is_numeric() && c != '_'))
+            };
+
+            let end = offset + c.len_utf16() + word.encode_utf16().count();
+            if let Some(token) = token {
+                tokens.push((offset, end, token));
+            }
+
+            offset = end;
+            i += 1 + length;
+            continue
+        }
+
+        offset += c.len_utf16();
+        i += 1;
+    }
+
+    tokens
+}
+
+/// Handle keys of the mentioned users and the hashtags of the text, without duplicates
+pub fn extract(text: &str) -> (Vec<String>, Vec<String>) {
+    let mut mentions = Vec::new();
+    let mut tags = Vec::new();
+
+    for (_, _, token) in tokenize(text) {
+        match token {
+            Token::Mention(handle) => mentions.push(handles::key(&handle)),
+            Token::Hashtag(tag) => tags.push(tag),
+        }
+    }
+    mentions.sort();
+    mentions.dedup();
+    tags.sort();
+    tags.dedup();
+
+    (mentions, tags)
+}
+
+/// Entities of the text. Only mentions of existing users are included, `mentioned` maps their handle keys to ids.
+pub fn parse(text: &str, mentioned: &HashMap<String, Uuid>) -> Vec<Entity> {
+    tokenize(text).into_iter()
+        .filter_map(|(start, end, token)| match token {
+            Token::Mention(handle) => mentioned.get(&handles::key(&handle))
+                .map(|user_id| Entity::Mention { start, end, handle, user_id: *user_id }),
+            Token::Hashtag(tag) => Some(Entity::Hashtag { start, end, tag }),
+        })
+        .collect()
+}
+
+/// Hashtags are stored lowercase and without the #
+pub fn normalize_tag(tag: &str) -> String {
+    tag.strip_prefix('#').unwrap_or(tag).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_mentions_and_hashtags() {
+        let (mentions, tags) = extract("Hi @Alice and @al1ce, see #Rust and #rust #2023 #web_dev");
+        assert_eq!(mentions, vec!["allce"]);
+        assert_eq!(tags, vec!["rust", "web_dev"]);
+    }
+
+    #[test]
+    fn ignores_emails_and_fragments() {
+        let (mentions, tags) = extract("mail bob@example.com or see page#section");
+        assert!(mentions.is_empty());
+        assert!(tags.is_empty());
+    }
+
+    #[test]
+    fn ranges_count_utf16_code_units() {
+        let mentioned = HashMap::from([("bob".to_string(), Uuid::nil())]);
+        let entities = parse("😀 @bob #café @nobody", &mentioned);
+
+        assert_eq!(entities, vec![
+            Entity::Mention { start: 3, end: 7, handle: "bob".to_string(), user_id: Uuid::nil() },
+            Entity::Hashtag { start: 8, end: 13, tag: "café".to_string() },
+        ]);
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream};
+use deadpool_postgres::{Manager, ManagerConfig, Pool, PoolError, RecyclingMethod};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_postgres::{Config, NoTls};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::db;
+use crate::db::errors::DbError;
+use crate::db::models::{Notification, PostUser};
+use crate::listener::Listener;
+use crate::tokens::{generate_token, hash_token};
+
+// Open sockets allowed for each user
+pub const MAX_SOCKETS_PER_USER: usize = 5;
+// Messages queued for each socket. Sockets that fall further behind are closed and resume after reconnecting.
+const SOCKET_BUFFER_SIZE: usize = 32;
+// Clients only send small messages
+const MAX_FRAME_SIZE: usize = 4 * 1024;
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+// Sockets that have sent nothing, not even a pong, for this long are closed
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+// The session is checked this often, so that users who logged out or were suspended or banned are disconnected
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// Users are shown online this long after their last socket closes, so that reconnecting goes unnoticed
+const PRESENCE_GRACE_PERIOD: Duration = Duration::from_secs(10);
+// Resume tokens can be used this long after their socket closes
+const RESUME_WINDOW: Duration = Duration::from_secs(2 * 60);
+// Typing messages sent more often than this are ignored
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+const NOTIFICATION_BATCH_SIZE: i64 = 100;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Message sent to the client as JSON
+#[derive(Serialize, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ServerMessage {
+    /// First message on every socket
+    #[serde(rename_all = "camelCase")]
+    Ready {
+        // Passed as `resume` when reconnecting to get the notifications missed in between
+        resume_token: String,
+        // False if no valid resume token was given, and missed notifications were not sent
+        resumed: bool,
+        // Followed users who are online
+        online: Vec<Uuid>,
+        unread_count: i64,
+    },
+    Notification {
+        notification: Notification,
+    },
+    /// Someone is writing a reply to a post of the user
+    #[serde(rename_all = "camelCase")]
+    Typing {
+        post_id: Uuid,
+        user: PostUser,
+    },
+    /// A followed user came online or went offline
+    #[serde(rename_all = "camelCase")]
+    Presence {
+        user_id: Uuid,
+        online: bool,
+    },
+    Error {
+        error: &'static str,
+    },
+}
+
+/// Message sent by the client as JSON
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    /// The user is writing a reply to the post
+    #[serde(rename_all = "camelCase")]
+    Typing {
+        post_id: Uuid,
+    },
+}
+
+struct Socket {
+    socket_id: u64,
+    sender: mpsc::Sender<ServerMessage>,
+}
+
+struct Resumable {
+    user_id: Uuid,
+    // Public id of the session the token was given to
+    public_id: Uuid,
+    // Newest notification sent on the socket
+    last_notification_id: i64,
+    // Tokens of open sockets cannot be used
+    closed_at: Option<Instant>,
+}
+
+#[derive(Default)]
+struct GatewayState {
+    // Users are online while they have sockets, and for the grace period after the last one closes
+    online: HashMap<Uuid, Vec<Socket>>,
+    // Keyed by the hash of the token
+    resumable: HashMap<String, Resumable>,
+    next_socket_id: u64,
+}
+
+/// Socket registered to the gateway
+pub struct Connection {
+    socket_id: u64,
+    token_hash: String,
+    // Notifications after this id are sent before anything else
+    resume_after: Option<i64>,
+    ready: ServerMessage,
+    receiver: mpsc::Receiver<ServerMessage>,
+}
+
+/// Keeps track of the open sockets and who is online
+#[derive(Clone)]
+pub struct Gateway {
+    state: Arc<Mutex<GatewayState>>,
+    pool: Pool,
+    // Cancelled when the server shuts down so that open sockets do not keep it running
+    shutdown: CancellationToken,
+}
+
+impl Gateway {
+    pub fn new(pool: Pool) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(GatewayState::default())),
+            pool,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Closes all open sockets
+    pub fn close(&self) {
+        self.shutdown.cancel();
+    }
+
+    pub fn socket_count(&self, user_id: &Uuid) -> usize {
+        self.state.lock().unwrap().online.get(user_id).map_or(0, Vec::len)
+    }
+
+    /// Sends the message to all sockets of the user. Sockets that cannot keep up are dropped.
+    fn send(&self, user_id: &Uuid, message: ServerMessage) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(sockets) = state.online.get_mut(user_id) {
+            sockets.retain(|socket| socket.sender.try_send(message.clone()).is_ok());
+        }
+    }
+
+    /// Registers the socket. The resume token is used if it belongs to the same session and has not expired.
+    pub async fn connect(&self, user: &PostUser, public_id: &Uuid, resume_token: Option<&str>) -> Result<Connection, DbError> {
+        let (sender, receiver) = mpsc::channel(SOCKET_BUFFER_SIZE);
+
+        let (socket_id, token, resume_after, came_online) = {
+            let mut state = self.state.lock().unwrap();
+            let socket_id = state.next_socket_id;
+            state.next_socket_id += 1;
+
+            let resumed = resume_token
+                .map(|token| (token.to_string(), hash_token(token)))
+                .and_then(|(token, token_hash)| {
+                    let resumable = state.resumable.get_mut(&token_hash)?;
+                    let valid = resumable.user_id == user.user_id && &resumable.public_id == public_id
+                        && resumable.closed_at.is_some_and(|closed_at| closed_at.elapsed() < RESUME_WINDOW);
+                    if !valid {
+                        return None
+                    }
+
+                    resumable.closed_at = None;
+                    Some((token, resumable.last_notification_id))
+                });
+
+            let (token, resume_after) = match resumed {
+                Some((token, last_notification_id)) => (token, Some(last_notification_id)),
+                None => {
+                    let token = generate_token(32);
+                    state.resumable.insert(hash_token(&token), Resumable {
+                        user_id: user.user_id,
+                        public_id: *public_id,
+                        last_notification_id: 0,
+                        closed_at: None,
+                    });
+                    (token, None)
+                }
+            };
+
+            // Users in the grace period have an empty list, and were never shown offline
+            let came_online = !state.online.contains_key(&user.user_id);
+            state.online.entry(user.user_id).or_default().push(Socket { socket_id, sender });
+
+            (socket_id, token, resume_after, came_online)
+        };
+
+        if came_online {
+            self.notify_followers(&user.user_id, true).await;
+        }
+
+        let token_hash = hash_token(&token);
+        let ready = match self.ready_message(user, token, resume_after.is_some()).await {
+            Ok(ready) => ready,
+            Err(err) => {
+                self.disconnect(&user.user_id, socket_id, &token_hash);
+                return Err(err)
+            }
+        };
+
+        Ok(Connection {
+            socket_id,
+            token_hash,
+            resume_after,
+            ready,
+            receiver,
+        })
+    }
+
+    async fn ready_message(&self, user: &PostUser, resume_token: String, resumed: bool) -> Result<ServerMessage, DbError> {
+        let client = self.get_client().await?;
+        let following = db::follows::get_following_ids(&client, &user.user_id).await?;
+        let unread_count = db::notifications::count_unread(&client, &user.user_id).await?;
+
+        // Read after the socket was registered, so newer notifications are sent to it
+        let newest_notification_id = if resumed { 0 } else { db::notifications::get_last_notification_id(&client).await? };
+
+        let mut state = self.state.lock().unwrap();
+        // Resuming with a new token only replays the notifications made after it was created
+        if let Some(resumable) = state.resumable.get_mut(&hash_token(&resume_token)) {
+            resumable.last_notification_id = resumable.last_notification_id.max(newest_notification_id);
+        }
+
+        Ok(ServerMessage::Ready {
+            resume_token,
+            resumed,
+            online: following.into_iter().filter(|user_id| state.online.contains_key(user_id)).collect(),
+            unread_count,
+        })
+    }
+
+    /// Unregisters the socket. The user goes offline if no socket is opened during the grace period.
+    fn disconnect(&self, user_id: &Uuid, socket_id: u64, token_hash: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(resumable) = state.resumable.get_mut(token_hash) {
+            resumable.closed_at = Some(Instant::now());
+        }
+        state.resumable.retain(|_, resumable| resumable.closed_at.is_none_or(|closed_at| closed_at.elapsed() < RESUME_WINDOW));
+
+        let Some(sockets) = state.online.get_mut(user_id) else { return };
+        sockets.retain(|socket| socket.socket_id != socket_id);
+        if !sockets.is_empty() {
+            return
+        }
+
+        let gateway = self.clone();
+        let user_id = *user_id;
+        tokio::spawn(async move {
+            tokio::time::sleep(PRESENCE_GRACE_PERIOD).await;
+
+            let went_offline = {
+                let mut state = gateway.state.lock().unwrap();
+                let offline = state.online.get(&user_id).is_some_and(Vec::is_empty);
+                if offline {
+                    state.online.remove(&user_id);
+                }
+                offline
+            };
+
+            if went_offline {
+                gateway.notify_followers(&user_id, false).await;
+            }
+        });
+    }
+
+    async fn notify_followers(&self, user_id: &Uuid, online: bool) {
+        let followers = match self.get_client().await {
+            Ok(client) => db::follows::get_follower_ids(&client, user_id).await,
+            Err(err) => Err(err),
+        };
+
+        match followers {
+            Ok(followers) => for follower_id in followers {
+                self.send(&follower_id, ServerMessage::Presence { user_id: *user_id, online });
+            },
+            Err(err) => debug!("Failed to send presence. {}", err),
+        }
+    }
+
+    fn acknowledge(&self, connection: &Connection, notification_id: i64) {
+        if let Some(resumable) = self.state.lock().unwrap().resumable.get_mut(&connection.token_hash) {
+            resumable.last_notification_id = notification_id;
+        }
+    }
+
+    async fn get_client(&self) -> Result<deadpool_postgres::Client, DbError> {
+        self.pool.get().await.map_err(|err| {
+            debug!("Failed to get connection: {}", err);
+            DbError::ConnectError
+        })
+    }
+
+    /// Serves the socket until either side closes it
+    pub async fn run_socket(self, mut session: actix_ws::Session, stream: MessageStream, user: PostUser, public_id: Uuid, mut connection: Connection) {
+        let mut stream = stream.max_frame_size(MAX_FRAME_SIZE);
+        let reason = self.serve(&mut session, &mut stream, &user, &public_id, &mut connection).await;
+
+        self.disconnect(&user.user_id, connection.socket_id, &connection.token_hash);
+        let _ = session.close(reason).await;
+    }
+
+    /// Returns the reason the socket is closed with, or None if the client is already gone
+    async fn serve(&self, session: &mut actix_ws::Session, stream: &mut MessageStream, user: &PostUser, public_id: &Uuid, connection: &mut Connection) -> Option<CloseReason> {
+        send(session, &connection.ready).await?;
+
+        let mut last_notification_id = 0;
+        if let Some(after) = connection.resume_after {
+            last_notification_id = after;
+            let client = self.get_client().await.ok()?;
+
+            loop {
+                let missed = db::notifications::get_new_notifications(&client, Some(&user.user_id), last_notification_id, NOTIFICATION_BATCH_SIZE).await.ok()?;
+                for (_, notification) in &missed {
+                    last_notification_id = notification.notification_id;
+                    send(session, &ServerMessage::Notification { notification: notification.clone() }).await?;
+                }
+                self.acknowledge(connection, last_notification_id);
+
+                if (missed.len() as i64) < NOTIFICATION_BATCH_SIZE {
+                    break;
+                }
+            }
+        }
+
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let mut session_check = tokio::time::interval_at(Instant::now() + SESSION_CHECK_INTERVAL, SESSION_CHECK_INTERVAL);
+        let mut last_seen = Instant::now();
+        let mut last_typing: Option<Instant> = None;
+
+        loop {
+            tokio::select! {
+                message = stream.recv() => {
+                    last_seen = Instant::now();
+
+                    match message {
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<ClientMessage>(&text) {
+                                Ok(ClientMessage::Typing { post_id }) => {
+                                    if last_typing.is_none_or(|at| at.elapsed() >= TYPING_INTERVAL) {
+                                        last_typing = Some(Instant::now());
+                                        self.send_typing(user, &post_id).await;
+                                    }
+                                }
+                                Err(_) => send(session, &ServerMessage::Error { error: "Invalid message" }).await?,
+                            }
+                        }
+                        Some(Ok(Message::Ping(bytes))) => session.pong(&bytes).await.ok()?,
+                        Some(Ok(Message::Pong(_))) => {},
+                        Some(Ok(Message::Close(_))) | None => return None,
+                        Some(Ok(_)) => return Some(CloseCode::Unsupported.into()),
+                        Some(Err(err)) => {
+                            debug!("WebSocket protocol error. {}", err);
+                            return Some(CloseCode::Protocol.into());
+                        }
+                    }
+                }
+
+                outgoing = connection.receiver.recv() => match outgoing {
+                    Some(ServerMessage::Notification { notification }) => {
+                        // Already sent while resuming
+                        if notification.notification_id <= last_notification_id {
+                            continue;
+                        }
+
+                        last_notification_id = notification.notification_id;
+                        send(session, &ServerMessage::Notification { notification }).await?;
+                        self.acknowledge(connection, last_notification_id);
+                    }
+                    Some(message) => send(session, &message).await?,
+                    // Dropped by the gateway because the client did not keep up
+                    None => return Some(CloseReason { code: CloseCode::Again, description: Some("Too slow".to_string()) }),
+                },
+
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > CLIENT_TIMEOUT {
+                        return Some(CloseReason { code: CloseCode::Away, description: Some("Timed out".to_string()) });
+                    }
+                    session.ping(b"").await.ok()?;
+                }
+
+                _ = session_check.tick() => {
+                    let active = match self.get_client().await {
+                        Ok(client) => db::sessions::is_session_active(&client, &user.user_id, public_id).await,
+                        Err(err) => Err(err),
+                    };
+
+                    match active {
+                        Ok(true) => {},
+                        Ok(false) => return Some(CloseReason { code: CloseCode::Policy, description: Some("Session ended".to_string()) }),
+                        Err(_) => return Some(CloseCode::Error.into()),
+                    }
+                }
+
+                _ = self.shutdown.cancelled() => {
+                    return Some(CloseCode::Restart.into());
+                }
+            }
+        }
+    }
+
+    /// Tells the author of the post that the user is replying to it
+    async fn send_typing(&self, user: &PostUser, post_id: &Uuid) {
+        let post = match self.get_client().await {
+            Ok(client) => db::posts::get_post(&client, post_id, None).await,
+            Err(err) => Err(err),
+        };
+
+        if let Ok(Some(post)) = post {
+            if post.user.user_id != user.user_id {
+                self.send(&post.user.user_id, ServerMessage::Typing { post_id: *post_id, user: user.clone() });
+            }
+        }
+    }
+}
+
+/// None if the socket is closed
+async fn send(session: &mut actix_ws::Session, message: &ServerMessage) -> Option<()> {
+    let text = serde_json::to_string(message).ok()?;
+    session.text(text).await.ok()
+}
+
+pub fn listen_notifications(config: Config, gateway: Gateway) -> (tokio::task::JoinHandle<Result<(), PoolError>>, CancellationToken) {
+    let cancel_token = CancellationToken::new();
+
+    (
+        tokio::spawn(listen_job(config, gateway, cancel_token.clone())),
+        cancel_token
+    )
+}
+
+async fn listen_job(config: Config, gateway: Gateway, cancel_token: CancellationToken) -> Result<(), PoolError> {
+    let mgr_config = ManagerConfig {
+        recycling_method: RecyclingMethod::Fast
+    };
+    let mgr = Manager::from_config(config.clone(), NoTls, mgr_config);
+    let pool = Pool::builder(mgr).max_size(1).build().unwrap();
+
+    let mut last_notification_id = None;
+
+    loop {
+        // The listener connection is reopened if it is lost
+        match Listener::connect(&config, "LISTEN notifications").await {
+            Ok(listener) => {
+                // Picks up the notifications created while the connection was down
+                deliver_notifications(&pool, &gateway, &mut last_notification_id).await?;
+
+                loop {
+                    tokio::select! {
+                        _ = listener.notified() => {
+                            deliver_notifications(&pool, &gateway, &mut last_notification_id).await?;
+                        }
+
+                        _ = listener.closed() => {
+                            break;
+                        }
+
+                        _ = cancel_token.cancelled() => {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            Err(err) => debug!("Failed to listen for notifications. {}", err),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {
+                continue;
+            }
+
+            _ = cancel_token.cancelled() => {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends the notifications after `last_notification_id` to the sockets of their users
+async fn deliver_notifications(pool: &Pool, gateway: &Gateway, last_notification_id: &mut Option<i64>) -> Result<(), PoolError> {
+    let client = pool.get().await?;
+
+    let mut after = match last_notification_id {
+        Some(id) => *id,
+        // Started just now, only newer notifications are sent
+        None => {
+            *last_notification_id = db::notifications::get_last_notification_id(&client).await.ok();
+            return Ok(());
+        }
+    };
+
+    loop {
+        let notifications = match db::notifications::get_new_notifications(&client, None, after, NOTIFICATION_BATCH_SIZE).await {
+            Ok(notifications) => notifications,
+            Err(err) => {
+                debug!("Failed to get new notifications. {}", err);
+                return Ok(())
+            }
+        };
+
+        for (user_id, notification) in &notifications {
+            after = notification.notification_id;
+            gateway.send(user_id, ServerMessage::Notification { notification: notification.clone() });
+        }
+        *last_notification_id = Some(after);
+
+        if (notifications.len() as i64) < NOTIFICATION_BATCH_SIZE {
+            return Ok(());
+        }
+    }
+}
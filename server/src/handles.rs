@@ -0,0 +1,101 @@
+use serde::{Deserialize, Deserializer};
+use validator::ValidationError;
+
+pub const MIN_LENGTH: usize = 3;
+pub const MAX_LENGTH: usize = 30;
+
+// Checked without regard to case or confusable characters, so e.g. "Adm1n" is reserved too
+const RESERVED: &[&str] = &[
+    "admin", "administrator", "api", "app", "auth", "root", "system", "support", "help", "security",
+    "moderator", "mod", "staff", "official", "login", "logout", "signup", "register", "settings",
+    "account", "user", "users", "me", "home", "search", "posts", "null", "undefined", "anonymous",
+    "everyone", "here", "www",
+];
+
+/// Cleans up a handle typed by a user: removes surrounding whitespace and a leading @,
+/// and turns fullwidth characters into their ASCII forms.
+pub fn normalize(input: &str) -> String {
+    let input = input.trim();
+    let input = input.strip_prefix('@').unwrap_or(input);
+
+    input.chars()
+        .map(|c| match c {
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c
+        })
+        .collect()
+}
+
+/// Two handles with the same key belong to the same user. Lowercases the handle and folds characters
+/// that look alike. Must match the `handle_key` column in the database.
+pub fn key(handle: &str) -> String {
+    handle.chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            '0' => 'o',
+            '1' | 'i' => 'l',
+            c => c
+        })
+        .collect()
+}
+
+pub fn is_reserved(handle: &str) -> bool {
+    let handle_key = key(handle);
+    RESERVED.iter().any(|reserved| key(reserved) == handle_key)
+}
+
+/// Handles have 3 to 30 ASCII letters, digits or underscores, at least one of them a letter.
+pub fn validate(handle: &str) -> Result<(), ValidationError> {
+    if handle.len() < MIN_LENGTH || handle.len() > MAX_LENGTH {
+        return Err(ValidationError::new("length"))
+    }
+
+    if !handle.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') || !handle.chars().any(|c| c.is_ascii_alphabetic()) {
+        return Err(ValidationError::new("handle"))
+    }
+
+    if is_reserved(handle) {
+        return Err(ValidationError::new("reserved"))
+    }
+
+    Ok(())
+}
+
+/// Deserializes a handle with `normalize` applied, for use with `#[serde(deserialize_with)]`
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(normalize(&String::deserialize(deserializer)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_typed_handles() {
+        assert_eq!(normalize("  @alice "), "alice");
+        assert_eq!(normalize("ａｌｉｃｅ"), "alice");
+    }
+
+    #[test]
+    fn confusable_handles_have_same_key() {
+        assert_eq!(key("Alice"), key("ALlCE"));
+        assert_eq!(key("b0b"), key("bob"));
+        assert_ne!(key("bob"), key("rob"));
+    }
+
+    #[test]
+    fn validates_handles() {
+        assert!(validate("alice_01").is_ok());
+        assert!(validate("al").is_err());
+        assert!(validate("alice bob").is_err());
+        assert!(validate("älice").is_err());
+        assert!(validate("12345").is_err());
+        assert!(validate(&"a".repeat(31)).is_err());
+    }
+
+    #[test]
+    fn reserved_handles_are_rejected() {
+        assert!(validate("admin").is_err());
+        assert!(validate("Adm1n").is_err());
+        assert!(validate("admins").is_ok());
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use futures_util::{stream, StreamExt};
+use log::debug;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio_postgres::{AsyncMessage, Client, Config, Connection, NoTls, Socket};
+use tokio_postgres::tls::NoTlsStream;
+use tokio_util::sync::CancellationToken;
+
+/// Connection of its own that wakes the background jobs on postgres notifications.
+/// Jobs read the rows they have not seen yet instead of relying on the payloads,
+/// so notifications that arrive close together are handled once.
+pub struct Listener {
+    // Closes the connection when dropped
+    _client: Client,
+    connection: JoinHandle<()>,
+    notify: Arc<Notify>,
+    // Cancelled when the connection is lost
+    closed: CancellationToken,
+}
+
+impl Listener {
+    /// `statement` is the LISTEN statement for the channels
+    pub async fn connect(config: &Config, statement: &'static str) -> Result<Self, tokio_postgres::Error> {
+        let (client, connection) = config.connect(NoTls).await?;
+        let notify = Arc::new(Notify::new());
+        let closed = CancellationToken::new();
+        let connection = tokio::spawn(forward_notifications(connection, notify.clone(), closed.clone()));
+
+        client.batch_execute(statement).await?;
+
+        Ok(Self {
+            _client: client,
+            connection,
+            notify,
+            closed,
+        })
+    }
+
+    pub async fn notified(&self) {
+        self.notify.notified().await;
+    }
+
+    /// Resolves when the connection is lost
+    pub async fn closed(&self) {
+        self.closed.cancelled().await;
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.connection.abort();
+    }
+}
+
+/// Drives the connection and wakes the listener on notifications. Returns when the connection is lost.
+async fn forward_notifications(mut connection: Connection<Socket, NoTlsStream>, notify: Arc<Notify>, closed: CancellationToken) {
+    let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+
+    while let Some(message) = messages.next().await {
+        match message {
+            Ok(AsyncMessage::Notification(_)) => notify.notify_one(),
+            Ok(_) => {},
+            Err(err) => {
+                debug!("Lost the listener connection. {}", err);
+                break;
+            }
+        }
+    }
+
+    closed.cancel();
+}
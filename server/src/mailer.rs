@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::Utc;
+use deadpool_postgres::{Manager, ManagerConfig, Pool, PoolError, RecyclingMethod};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::message::Mailbox;
+use log::{debug, info};
+use tokio_postgres::{Config, NoTls};
+use tokio_util::sync::CancellationToken;
+
+use crate::db;
+use crate::tokens::generate_token;
+
+// Emails are only retried this many times before they are left in the outbox for manual inspection
+pub const MAX_SEND_ATTEMPTS: i32 = 5;
+const OUTBOX_BATCH_SIZE: i64 = 20;
+// Claimed emails that have not been marked as sent after this time are sent again by any instance
+const OUTBOX_CLAIM_TIMEOUT_SECONDS: i64 = 10 * 60;
+
+pub struct Email {
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), anyhow::Error>;
+}
+
+/// Sends emails through an SMTP server. The url format is described in
+/// https://docs.rs/lettre/latest/lettre/transport/smtp/struct.AsyncSmtpTransport.html#method.from_url
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(url: &str, from: &str) -> Result<SmtpMailer, anyhow::Error> {
+        Ok(SmtpMailer {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::from_url(url)?.build(),
+            from: from.parse()?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), anyhow::Error> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(email.recipient.parse()?)
+            .subject(&email.subject)
+            .body(email.body.clone())?;
+
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Writes emails as files to a directory. Meant for local development.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> FileMailer {
+        FileMailer { dir }
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), anyhow::Error> {
+        let path = self.dir.join(format!("{}-{}.txt", Utc::now().timestamp_millis(), generate_token(8)));
+        let content = format!("To: {}\nSubject: {}\n\n{}\n", email.recipient, email.subject, email.body);
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(&path, content).await?;
+        Ok(())
+    }
+}
+
+/// Writes emails to the log. Meant for local development.
+pub struct LogMailer;
+
+#[async_trait::async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: &Email) -> Result<(), anyhow::Error> {
+        info!("Email to {}\nSubject: {}\n\n{}", email.recipient, email.subject, email.body);
+        Ok(())
+    }
+}
+
+/// Creates the mailer based on the MAILER environment variable. Defaults to logging emails.
+pub fn mailer_from_env() -> Result<Arc<dyn Mailer>, anyhow::Error> {
+    let backend = std::env::var("MAILER").unwrap_or_else(|_| "log".to_string());
+
+    match backend.as_str() {
+        "smtp" => Ok(Arc::new(SmtpMailer::new(
+            &std::env::var("SMTP_URL").map_err(|_| anyhow::anyhow!("SMTP_URL must be defined when using the smtp mailer"))?,
+            &std::env::var("MAIL_FROM").map_err(|_| anyhow::anyhow!("MAIL_FROM must be defined when using the smtp mailer"))?,
+        )?)),
+        "file" => Ok(Arc::new(FileMailer::new(
+            std::env::var("MAIL_DIR").unwrap_or_else(|_| "mail".to_string()).into()
+        ))),
+        "log" => Ok(Arc::new(LogMailer)),
+        _ => Err(anyhow::anyhow!("Unknown mailer {}", backend))
+    }
+}
+
+pub fn send_outbox(config: Config, mailer: Arc<dyn Mailer>) -> (tokio::task::JoinHandle<Result<(), PoolError>>, CancellationToken) {
+    let cancel_token = CancellationToken::new();
+
+    (
+        tokio::spawn(outbox_job(config, mailer, cancel_token.clone())),
+        cancel_token
+    )
+}
+
+async fn outbox_job(config: Config, mailer: Arc<dyn Mailer>, cancel_token: CancellationToken) -> Result<(), PoolError> {
+    let mgr_config = ManagerConfig {
+        recycling_method: RecyclingMethod::Fast
+    };
+    let mgr = Manager::from_config(config, NoTls, mgr_config);
+    let pool = Pool::builder(mgr).max_size(1).build().unwrap();
+
+    loop {
+        let client = pool.get().await?;
+        if let Err(err) = db::outbox::delete_expired_emails(&client).await {
+            debug!("Failed to delete expired emails from the outbox. {}", err);
+        }
+        if let Err(err) = db::outbox::send_pending_emails(&client, mailer.as_ref(), OUTBOX_BATCH_SIZE, OUTBOX_CLAIM_TIMEOUT_SECONDS).await {
+            debug!("Failed to send emails from the outbox. {}", err);
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(10)) => {
+                continue;
+            }
+
+            _ = cancel_token.cancelled() => {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,4 +1,8 @@
+use std::sync::Arc;
+
+#[cfg(debug_assertions)]
 use actix_cors::Cors;
+#[cfg(not(debug_assertions))]
 use actix_files as fs;
 use actix_session::config::PersistentSession;
 use actix_session::SessionMiddleware;
@@ -9,15 +13,34 @@ use data_encoding::BASE64;
 use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
 use dotenv::dotenv;
 use tokio_postgres::NoTls;
+use webauthn_rs::prelude::Url;
 
 use crate::db::session_store::{clear_old_sessions, PostgresSessionStore};
+use crate::gateway::{Gateway, listen_notifications};
+use crate::mailer::send_outbox;
 use crate::middleware::CsrfMiddleware;
-use crate::models::AppState;
+use crate::models::{AppState, env_or, LoginThrottleConfig};
+use crate::password::PasswordHasher;
+use crate::post_stream::{listen_post_events, PostStream};
+use crate::proxy::TrustedProxies;
+use crate::tokens::derive_key;
 
 mod db;
 mod models;
 mod api;
+mod audit;
+mod entities;
+mod gateway;
+mod handles;
+mod listener;
+mod mailer;
 mod middleware;
+mod password;
+mod post_stream;
+mod proxy;
+mod tokens;
+mod totp;
+mod webauthn;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -33,13 +56,28 @@ async fn main() -> std::io::Result<()> {
         },
     };
 
-    let secret_key = match std::env::var("SESSION_SECRET") {
-        Ok(val) => Key::from(val.as_bytes()),
+    let session_secret = match std::env::var("SESSION_SECRET") {
+        Ok(val) => val,
         Err(_) => {
             eprintln!("SESSION_SECRET environment variable must be defined");
             return Ok(())
         }
     };
+    let secret_key = Key::from(session_secret.as_bytes());
+    let email_token_key = derive_key(session_secret.as_bytes(), "email-verification");
+
+    let require_verified_email = std::env::var("REQUIRE_VERIFIED_EMAIL")
+        .map(|val| val == "true")
+        .unwrap_or(false);
+    let login_throttle = LoginThrottleConfig::from_env();
+    let trusted_proxies = TrustedProxies::parse(&std::env::var("TRUSTED_PROXIES").unwrap_or_default())
+        .expect("TRUSTED_PROXIES should be a comma separated list of ip addresses");
+    // Defaults follow the OWASP password storage cheat sheet
+    let password_hasher = PasswordHasher::new(
+        env_or("ARGON2_MEMORY_KIB", 19 * 1024),
+        env_or("ARGON2_ITERATIONS", 2),
+        env_or("ARGON2_PARALLELISM", 1)
+    ).expect("Invalid argon2 parameters");
 
     let csrf_secret: [u8; 32] = match std::env::var("CSRF_SECRET") {
         Ok(val) => BASE64.decode(val.as_bytes())
@@ -52,6 +90,23 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
+    let app_url = match std::env::var("APP_URL") {
+        Ok(val) => Url::parse(&val).expect("APP_URL should be a valid url"),
+        Err(_) => {
+            eprintln!("APP_URL environment variable must be defined");
+            return Ok(())
+        }
+    };
+
+    let webauthn = Arc::new(webauthn::build_webauthn(&app_url).expect("Failed to build webauthn configuration"));
+
+    let mailer = match mailer::mailer_from_env() {
+        Ok(mailer) => mailer,
+        Err(err) => {
+            eprintln!("Failed to configure mailer. {}", err);
+            return Ok(())
+        }
+    };
 
     let config = config_string.as_str().parse::<tokio_postgres::Config>()
         .map_err( |_| std::io::Error::new(std::io::ErrorKind::NotConnected, "Failed to connect to postgres"))?;
@@ -67,17 +122,35 @@ async fn main() -> std::io::Result<()> {
         .await
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::NotConnected, "Failed to connect to postgres"))?;
 
-    let (handle, cance_token) = clear_old_sessions(config);
+    let (handle, cance_token) = clear_old_sessions(config.clone(), login_throttle.reset_after_seconds);
+    let (outbox_handle, outbox_cancel_token) = send_outbox(config.clone(), mailer);
+    let post_stream = PostStream::new();
+    let (listen_handle, listen_cancel_token) = listen_post_events(config.clone(), post_stream.clone());
+    let gateway = Gateway::new(pool.clone());
+    let (gateway_handle, gateway_cancel_token) = listen_notifications(config, gateway.clone());
+    let app_post_stream = post_stream.clone();
+    let app_gateway = gateway.clone();
 
-    // Json error handler for actix-web-validator
+    // Json and query error handlers for actix-web-validator
     let json_config = api::errors::generate_json_config();
+    let query_config = api::errors::generate_query_config();
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         let app = App::new()
             .app_data(web::Data::new(AppState {
-                pool: pool.clone()
+                pool: pool.clone(),
+                webauthn: webauthn.clone(),
+                app_url: app_url.clone(),
+                email_token_key: email_token_key.clone(),
+                require_verified_email,
+                login_throttle: login_throttle.clone(),
+                trusted_proxies: trusted_proxies.clone(),
+                password_hasher: password_hasher.clone(),
+                post_stream: app_post_stream.clone(),
+                gateway: app_gateway.clone(),
             }))
             .app_data(json_config.clone())
+            .app_data(query_config.clone())
             // Middleware is executed in reverse order
             .wrap(CsrfMiddleware::new(&csrf_secret))
             .wrap(SessionMiddleware::builder(
@@ -88,9 +161,21 @@ async fn main() -> std::io::Result<()> {
                 .build()
             )
             .wrap(Logger::default())
+            .service(web::scope("/api/admin").configure(api::admin::config))
+            .service(web::scope("/api/auth/webauthn").configure(api::webauthn::config))
             .service(web::scope("/api/auth").configure(api::auth::config))
-            .service(web::scope("/api/posts").configure(api::posts::config))
-            .service(web::scope("/api/user").configure(api::user::config));
+            .service(web::scope("/api/gateway").configure(api::gateway::config))
+            .service(web::scope("/api/notifications").configure(api::notifications::config))
+            .service(web::scope("/api/posts")
+                .configure(api::posts::config)
+                .configure(api::reactions::config))
+            .service(web::scope("/api/user/2fa").configure(api::two_factor::config))
+            .service(web::scope("/api/user/tokens").configure(api::tokens::config))
+            .service(web::scope("/api/user/follows").configure(api::follows::config))
+            .service(web::scope("/api/user")
+                .configure(api::user::config)
+                // Registered last, so that `/{user_id}` does not shadow the other routes
+                .configure(api::profiles::config));
 
         #[cfg(not(debug_assertions))]
         return app
@@ -104,11 +189,45 @@ async fn main() -> std::io::Result<()> {
             .wrap(Cors::permissive());
     })
     .bind(("127.0.0.1", 8080))?
-    .run()
-    .await?;
+    // Signals are handled below, so that open streams and sockets can be closed before the graceful shutdown
+    .disable_signals()
+    .run();
+
+    let server_handle = server.handle();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        post_stream.close();
+        gateway.close();
+        server_handle.stop(true).await;
+    });
+
+    server.await?;
 
     cance_token.cancel();
+    outbox_cancel_token.cancel();
+    listen_cancel_token.cancel();
+    gateway_cancel_token.cancel();
     let _ = handle.await.unwrap();
+    let _ = outbox_handle.await.unwrap();
+    let _ = listen_handle.await.unwrap();
+    let _ = gateway_handle.await.unwrap();
 
     Ok(())
 }
+
+/// Resolves on Ctrl-C, or SIGTERM on unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -5,10 +5,11 @@ use std::future::{ready, Ready};
 use std::rc::Rc;
 
 use actix_session::{Session, SessionExt};
-use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError, web};
 use actix_web::body::EitherBody;
 use actix_web::dev::{Extensions, Payload};
 use actix_web::http::{Method, StatusCode};
+use actix_web::http::header::ORIGIN;
 // AES not used as it might be vulnerable to timing based attacks and is more brittle overall.
 // Might not perform as well but that is of no concern.
 use csrf::{ChaCha20Poly1305CsrfProtection, CsrfProtection};
@@ -18,8 +19,12 @@ use futures_util::future::LocalBoxFuture;
 use futures_util::FutureExt;
 use log::debug;
 use serde::Serialize;
+use serde_json::json;
 
 use crate::api::errors::ApiError;
+use crate::api::utilities::bearer_token;
+use crate::audit::{self, EventType};
+use crate::models::AppState;
 
 // There are two steps in middleware processing.
 // 1. Middleware initialization, middleware factory gets called with
@@ -37,9 +42,9 @@ struct Config {
 impl CsrfMiddleware {
     pub fn new(secret: &[u8; 32]) -> Self {
         Self(Rc::new(Config {
-            secret: secret.clone(),
+            secret: *secret,
             exclude: HashSet::from(["/api/auth/csrf".to_string()]),
-            methods: HashSet::from([Method::POST, Method::PUT, Method::DELETE])
+            methods: HashSet::from([Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
         }))
     }
 }
@@ -60,9 +65,9 @@ impl Csrf {
     }
 
     pub(crate) fn set_csrf(req: &mut ServiceRequest, secret: &[u8; 32]) {
-        let csrf = Csrf::get_csrf(&mut *req.extensions_mut());
+        let csrf = Csrf::get_csrf(&mut req.extensions_mut());
         let mut inner = csrf.0.borrow_mut();
-        inner.secret = secret.clone();
+        inner.secret = *secret;
     }
 
     /// Generates a new session token. Tries to use the previous token as the base if it can be parsed.
@@ -117,7 +122,7 @@ impl FromRequest for Csrf {
 
     #[inline]
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
-        ready(Ok(Csrf::get_csrf(&mut *req.extensions_mut())))
+        ready(Ok(Csrf::get_csrf(&mut req.extensions_mut())))
     }
 }
 
@@ -128,6 +133,12 @@ enum CsrfError {
 
     #[display(fmt = "CSRF token is invalid")]
     CsrfInvalid,
+
+    #[display(fmt = "Origin is missing")]
+    OriginMissing,
+
+    #[display(fmt = "Origin is not allowed")]
+    OriginInvalid,
 }
 
 #[derive(Serialize)]
@@ -197,63 +208,120 @@ where
         async move {
             Csrf::set_csrf(&mut req, &config.secret);
 
-            if !config.methods.contains(&req.method()) || config.exclude.contains(req.path()) {
-                return Ok(srv.call(req).await.map(ServiceResponse::map_into_left_body)?);
+            if !config.methods.contains(req.method()) || config.exclude.contains(req.path()) {
+                return srv.call(req).await.map(ServiceResponse::map_into_left_body);
             }
 
 
             // Get the session cookie value, if it exists.
             let sess = req.get_session();
-            let csrf = match req.headers().get("X-CSRF-TOKEN") {
-                Some(csrf) =>
-                    match BASE64.decode(csrf.as_bytes()) {
-                        Ok(v) => v,
-                        Err(err) => {
-                            debug!("Failed to decode csrf token from header. {}", err);
-                            // Errors must be done like this since throwing them discards the body.
-                            // With this method the body is included
-                            return Ok(req.error_response(CsrfError::CsrfInvalid).map_into_right_body())
-                        }
-                    },
-                None => return Ok(req.error_response(CsrfError::CsrfMissing).map_into_right_body())
-            };
-
-            let saved_csrf = match sess.get::<String>("csrf")? {
-                Some(csrf) =>
-                    match BASE64.decode(csrf.as_bytes()) {
-                        Ok(v) => v,
-                        Err(err) => {
-                            debug!("Failed to decode csrf token from session. {}", err);
-                            return Ok(req.error_response(CsrfError::CsrfInvalid).map_into_right_body())
-                        }
-                    },
-                None => return Ok(req.error_response(CsrfError::CsrfMissing).map_into_right_body())
-            };
-
-            let protection = ChaCha20Poly1305CsrfProtection::from_key(config.secret);
-
-            let parsed_csrf = match protection.parse_cookie(&csrf) {
-                Ok(v) => v,
-                Err(err) => {
-                    debug!("Failed to parse csrf token from header. {}", err);
-                    return Ok(req.error_response(CsrfError::CsrfInvalid).map_into_right_body());
-                }
-            };
 
-            let parsed_token = match protection.parse_token(&saved_csrf){
-                Ok(v) => v,
-                Err(err) => {
-                    debug!("Failed to parse csrf token from session. {}", err);
-                    return Ok(req.error_response(CsrfError::CsrfInvalid).map_into_right_body());
-                }
-            };
+            // Browsers do not attach the authorization header on their own, so requests authenticated with
+            // an api token cannot be forged. The check is still done if the request also carries a logged-in session.
+            if bearer_token(req.headers()).is_some() && sess.get::<uuid::Uuid>("user_id")?.is_none() {
+                return srv.call(req).await.map(ServiceResponse::map_into_left_body);
+            }
 
-            if !protection.verify_token_pair(&parsed_token, &parsed_csrf) {
-                return Ok(req.error_response(CsrfError::CsrfInvalid).map_into_right_body());
+            if let Err(err) = verify_token(&req, &sess, &config.secret) {
+                record_rejection(req.request(), &sess, &err).await;
+                // Errors must be done like this since throwing them discards the body.
+                // With this method the body is included
+                return Ok(req.error_response(err).map_into_right_body())
             }
 
-            Ok(srv.call(req).await.map(ServiceResponse::map_into_left_body)?)
+            srv.call(req).await.map(ServiceResponse::map_into_left_body)
         }
         .boxed_local()
     }
 }
+
+/// Checks the token in the header against the one saved to the session.
+fn verify_token(req: &ServiceRequest, sess: &Session, secret: &[u8; 32]) -> Result<(), CsrfError> {
+    let csrf = match req.headers().get("X-CSRF-TOKEN") {
+        Some(csrf) => BASE64.decode(csrf.as_bytes())
+            .map_err(|err| {
+                debug!("Failed to decode csrf token from header. {}", err);
+                CsrfError::CsrfInvalid
+            })?,
+        None => return Err(CsrfError::CsrfMissing)
+    };
+
+    let saved_csrf = sess.get::<String>("csrf")
+        .map_err(|err| {
+            debug!("Failed to get csrf token from session. {}", err);
+            CsrfError::CsrfInvalid
+        })?;
+    let saved_csrf = match saved_csrf {
+        Some(csrf) => BASE64.decode(csrf.as_bytes())
+            .map_err(|err| {
+                debug!("Failed to decode csrf token from session. {}", err);
+                CsrfError::CsrfInvalid
+            })?,
+        None => return Err(CsrfError::CsrfMissing)
+    };
+
+    let protection = ChaCha20Poly1305CsrfProtection::from_key(*secret);
+
+    let parsed_csrf = protection.parse_cookie(&csrf)
+        .map_err(|err| {
+            debug!("Failed to parse csrf token from header. {}", err);
+            CsrfError::CsrfInvalid
+        })?;
+
+    let parsed_token = protection.parse_token(&saved_csrf)
+        .map_err(|err| {
+            debug!("Failed to parse csrf token from session. {}", err);
+            CsrfError::CsrfInvalid
+        })?;
+
+    if !protection.verify_token_pair(&parsed_token, &parsed_csrf) {
+        return Err(CsrfError::CsrfInvalid)
+    }
+
+    Ok(())
+}
+
+/// Protects requests that cannot carry the token header, like WebSocket handshakes.
+/// Browsers always send the origin of the page with them, so it must be the frontend.
+pub async fn verify_origin(req: &HttpRequest, sess: &Session) -> Result<(), Error> {
+    let app_url = match req.app_data::<web::Data<AppState>>() {
+        Some(data) => data.app_url.origin().ascii_serialization(),
+        None => return Err(ApiError::InternalServerError.into())
+    };
+
+    let result = match req.headers().get(ORIGIN) {
+        Some(origin) if origin.as_bytes() == app_url.as_bytes() => Ok(()),
+        Some(_) => Err(CsrfError::OriginInvalid),
+        None => Err(CsrfError::OriginMissing),
+    };
+
+    if let Err(err) = result {
+        record_rejection(req, sess, &err).await;
+        return Err(err.into())
+    }
+
+    Ok(())
+}
+
+/// Writes the rejected request to the audit log. Failing to do so does not change the response.
+/// Only requests with a logged in session are recorded, so that anonymous requests can not fill the log.
+async fn record_rejection(req: &HttpRequest, sess: &Session, err: &CsrfError) {
+    let user_id = match sess.get::<uuid::Uuid>("user_id").ok().flatten() {
+        Some(user_id) => user_id,
+        None => return
+    };
+    let data = match req.app_data::<web::Data<AppState>>() {
+        Some(data) => data,
+        None => return
+    };
+    let details = json!({ "method": req.method().as_str(), "path": req.path(), "reason": err.to_string() });
+
+    let result = match data.get_client().await {
+        Ok(client) => audit::record(&client, req, EventType::CsrfRejected, Some(&user_id), None, details).await,
+        Err(err) => Err(err)
+    };
+
+    if let Err(err) = result {
+        debug!("Failed to record csrf rejection. {}", err);
+    }
+}
@@ -1,11 +1,70 @@
+use std::sync::Arc;
+
 use deadpool_postgres::{Client, Pool};
 use log::debug;
+use webauthn_rs::prelude::Url;
+use webauthn_rs::Webauthn;
 
 use crate::db::errors::DbError;
+use crate::gateway::Gateway;
+use crate::password::PasswordHasher;
+use crate::post_stream::PostStream;
+use crate::proxy::TrustedProxies;
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: Pool,
+    pub webauthn: Arc<Webauthn>,
+    // Base url of the frontend used in links sent to users
+    pub app_url: Url,
+    // Key used to sign email verification tokens
+    pub email_token_key: Vec<u8>,
+    // Users cannot log in or create posts before verifying their email if this is set
+    pub require_verified_email: bool,
+    pub login_throttle: LoginThrottleConfig,
+    // Used to resolve the ip address of clients behind a reverse proxy
+    pub trusted_proxies: TrustedProxies,
+    pub password_hasher: PasswordHasher,
+    pub post_stream: PostStream,
+    pub gateway: Gateway,
+}
+
+#[derive(Clone)]
+pub struct LoginThrottleConfig {
+    // Failed logins allowed before the account or ip gets locked
+    pub account_threshold: i32,
+    pub ip_threshold: i32,
+    // Password reset requests allowed for an email or ip before further requests are refused
+    pub reset_email_threshold: i32,
+    pub reset_ip_threshold: i32,
+    // Verification and other account emails sent to an address before further ones are held back
+    pub account_email_threshold: i32,
+    // Lockout time after reaching the threshold. Doubles with every further failure up to the max.
+    pub base_lockout_seconds: i64,
+    pub max_lockout_seconds: i64,
+    // Failures are forgotten if there have been none during this time
+    pub reset_after_seconds: i64,
+}
+
+pub fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(default)
+}
+
+impl LoginThrottleConfig {
+    pub fn from_env() -> LoginThrottleConfig {
+        LoginThrottleConfig {
+            account_threshold: env_or("LOGIN_ACCOUNT_THRESHOLD", 5),
+            ip_threshold: env_or("LOGIN_IP_THRESHOLD", 20),
+            reset_email_threshold: env_or("RESET_EMAIL_THRESHOLD", 3),
+            reset_ip_threshold: env_or("RESET_IP_THRESHOLD", 10),
+            account_email_threshold: env_or("ACCOUNT_EMAIL_THRESHOLD", 3),
+            base_lockout_seconds: env_or("LOGIN_LOCKOUT_SECONDS", 30),
+            max_lockout_seconds: env_or("LOGIN_MAX_LOCKOUT_SECONDS", 60 * 60),
+            reset_after_seconds: 24 * 60 * 60,
+        }
+    }
 }
 
 impl AppState {
@@ -0,0 +1,179 @@
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher as _, PasswordVerifier, Version};
+use argon2::password_hash::SaltString;
+use derive_more::{Display, Error};
+use log::debug;
+use rand::rngs::OsRng;
+
+use crate::tokens::generate_token;
+
+/// The details are logged where the error happens
+#[derive(Debug, Display, Error)]
+pub enum PasswordError {
+    #[display(fmt = "Failed to hash password")]
+    HashFailed,
+
+    #[display(fmt = "Failed to run password hashing")]
+    Blocking,
+}
+
+/// Argon2id parameters used for new hashes.
+#[derive(Clone)]
+pub struct PasswordHasher {
+    params: Params,
+    // Hash of a random password. Checked when the user does not exist so that the response takes as long as for real users.
+    dummy_hash: String,
+}
+
+pub struct Verification {
+    pub valid: bool,
+    // The hash was made with bcrypt or with different parameters and should be replaced
+    pub needs_rehash: bool,
+}
+
+impl PasswordHasher {
+    pub fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> Result<PasswordHasher, argon2::password_hash::Error> {
+        let mut hasher = PasswordHasher {
+            params: Params::new(memory_kib, iterations, parallelism, None)?,
+            dummy_hash: String::new(),
+        };
+        hasher.dummy_hash = hasher.hash_blocking(&generate_token(32))?;
+
+        Ok(hasher)
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    fn hash_blocking(&self, password: &str) -> Result<String, argon2::password_hash::Error> {
+        let salt = SaltString::generate(&mut OsRng);
+        Ok(self.argon2().hash_password(password.as_bytes(), &salt)?.to_string())
+    }
+
+    fn verify_blocking(&self, password: &str, hash: &str) -> Verification {
+        // Hashes created by pgcrypto before the migration to argon2
+        if hash.starts_with("$2") {
+            let valid = bcrypt::verify(password, hash).unwrap_or(false);
+            // The old bcrypt hashes are much faster to check than argon2, which would reveal existing accounts
+            if let Ok(dummy) = PasswordHash::new(&self.dummy_hash) {
+                let _ = self.argon2().verify_password(password.as_bytes(), &dummy);
+            }
+
+            return Verification { valid, needs_rehash: true }
+        }
+
+        let parsed = match PasswordHash::new(hash) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                debug!("Failed to parse password hash. {}", err);
+                return Verification { valid: false, needs_rehash: false }
+            }
+        };
+
+        let valid = self.argon2().verify_password(password.as_bytes(), &parsed).is_ok();
+        let needs_rehash = parsed.algorithm != Algorithm::Argon2id.ident()
+            || Params::try_from(&parsed)
+                .map(|params| params.m_cost() != self.params.m_cost()
+                    || params.t_cost() != self.params.t_cost()
+                    || params.p_cost() != self.params.p_cost())
+                .unwrap_or(true);
+
+        Verification { valid, needs_rehash }
+    }
+
+    /// Hashes the password into a PHC string. Hashing is slow on purpose, so it is done on a blocking thread.
+    pub async fn hash(&self, password: &str) -> Result<String, PasswordError> {
+        let hasher = self.clone();
+        let password = password.to_string();
+
+        actix_web::web::block(move || hasher.hash_blocking(&password))
+            .await
+            .map_err(|err| {
+                debug!("Failed to run password hashing. {}", err);
+                PasswordError::Blocking
+            })?
+            .map_err(|err| {
+                debug!("Failed to hash password. {}", err);
+                PasswordError::HashFailed
+            })
+    }
+
+    pub async fn verify(&self, password: &str, hash: &str) -> Result<Verification, PasswordError> {
+        let hasher = self.clone();
+        let password = password.to_string();
+        let hash = hash.to_string();
+
+        actix_web::web::block(move || hasher.verify_blocking(&password, &hash))
+            .await
+            .map_err(|err| {
+                debug!("Failed to run password verification. {}", err);
+                PasswordError::Blocking
+            })
+    }
+
+    /// Does the same amount of work as verifying a real password. The result is always discarded.
+    pub async fn verify_dummy(&self, password: &str) -> Result<(), PasswordError> {
+        self.verify(password, &self.dummy_hash).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small parameters keep the tests fast
+    fn hasher() -> PasswordHasher {
+        PasswordHasher::new(1024, 1, 1).unwrap()
+    }
+
+    #[test]
+    fn argon2id_hashes_round_trip() {
+        let hasher = hasher();
+        let hash = hasher.hash_blocking("correct horse").unwrap();
+
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        let verification = hasher.verify_blocking("correct horse", &hash);
+        assert!(verification.valid);
+        assert!(!verification.needs_rehash);
+        assert!(!hasher.verify_blocking("wrong horse", &hash).valid);
+    }
+
+    #[test]
+    fn salts_are_random() {
+        let hasher = hasher();
+
+        assert_ne!(hasher.hash_blocking("correct horse").unwrap(), hasher.hash_blocking("correct horse").unwrap());
+    }
+
+    #[test]
+    fn changed_parameters_need_rehash() {
+        let hash = hasher().hash_blocking("correct horse").unwrap();
+
+        for changed in [PasswordHasher::new(2048, 1, 1), PasswordHasher::new(1024, 2, 1), PasswordHasher::new(1024, 1, 2)] {
+            let verification = changed.unwrap().verify_blocking("correct horse", &hash);
+            assert!(verification.valid);
+            assert!(verification.needs_rehash);
+        }
+    }
+
+    #[test]
+    fn verifies_legacy_bcrypt_hashes() {
+        // Created by pgcrypto with crypt('correct horse', gen_salt('bf', 4))
+        let hash = "$2a$04$82XmqkhVKOeCbxPxRounIe.ukvd1Nsl3tLgrT03.QRZYdz5K0FpAa";
+        let hasher = hasher();
+
+        let verification = hasher.verify_blocking("correct horse", hash);
+        assert!(verification.valid);
+        assert!(verification.needs_rehash);
+        assert!(!hasher.verify_blocking("wrong horse", hash).valid);
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        let verification = hasher().verify_blocking("correct horse", "not a hash");
+
+        assert!(!verification.valid);
+        assert!(!verification.needs_rehash);
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use actix_web::web::Bytes;
+use deadpool_postgres::{Manager, ManagerConfig, Pool, PoolError, RecyclingMethod};
+use futures_util::{Stream, stream};
+use log::debug;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_postgres::{Config, NoTls};
+use tokio_util::sync::CancellationToken;
+
+use crate::db;
+use crate::db::errors::DbError;
+use crate::db::models::{PostEvent, PostEventKind};
+use crate::listener::Listener;
+
+// Events buffered for each client. Clients that fall further behind catch up from the database.
+const CLIENT_BUFFER_SIZE: usize = 64;
+const EVENT_BATCH_SIZE: i64 = 100;
+// Comments are sent this often so that proxies keep idle streams open and closed streams are noticed
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Event formatted for the stream
+#[derive(Clone)]
+pub struct StreamEvent {
+    event_id: i64,
+    // None if the created post has been deleted before the event was read
+    frame: Option<Bytes>,
+}
+
+impl From<&PostEvent> for StreamEvent {
+    fn from(event: &PostEvent) -> Self {
+        let data = match event.kind {
+            PostEventKind::Created => event.post.as_ref().and_then(|post| serde_json::to_string(post).ok()),
+            PostEventKind::Deleted => Some(json!({ "postId": event.post_id }).to_string()),
+        };
+        let name = match event.kind {
+            PostEventKind::Created => "created",
+            PostEventKind::Deleted => "deleted",
+        };
+
+        Self {
+            event_id: event.event_id,
+            frame: data.map(|data| Bytes::from(format!("id: {}\nevent: {}\ndata: {}\n\n", event.event_id, name, data))),
+        }
+    }
+}
+
+impl StreamEvent {
+    /// Tells a client that resumed from an event that has already been deleted to reload the posts,
+    /// since the events it missed cannot be sent anymore
+    fn reset(event_id: i64) -> Self {
+        Self {
+            event_id,
+            frame: Some(Bytes::from(format!("id: {}\nevent: reset\ndata: {{}}\n\n", event_id))),
+        }
+    }
+}
+
+/// Shares the post events read by the listener job with the open streams
+#[derive(Clone)]
+pub struct PostStream {
+    sender: broadcast::Sender<StreamEvent>,
+    // Cancelled when the server shuts down so that open streams do not keep it running
+    shutdown: CancellationToken,
+}
+
+impl PostStream {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CLIENT_BUFFER_SIZE);
+
+        Self {
+            sender,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Ends all open streams
+    pub fn close(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Streams the events after `last_event_id`, or only new events if it is None.
+    /// A database connection is only taken from the pool while the client is catching up.
+    pub async fn subscribe(&self, pool: Pool, last_event_id: Option<i64>) -> Result<impl Stream<Item=Result<Bytes, actix_web::Error>>, DbError> {
+        // Subscribe before reading the newest event, so that nothing is missed in between
+        let receiver = self.sender.subscribe();
+        let client = pool.get().await.map_err(|err| {
+            debug!("Failed to get connection: {}", err);
+            DbError::ConnectError
+        })?;
+        let newest = db::posts::get_last_post_event_id(&client).await?;
+        let pruned = db::posts::get_pruned_post_event_id(&client).await?;
+
+        let mut backlog = VecDeque::new();
+        let last_event_id = match last_event_id {
+            Some(id) if id < pruned => {
+                backlog.push_back(StreamEvent::reset(newest));
+                newest
+            },
+            Some(id) => id.min(newest),
+            None => newest
+        };
+
+        let subscriber = Subscriber {
+            pool,
+            receiver,
+            last_event_id,
+            catching_up: last_event_id < newest,
+            backlog,
+            shutdown: self.shutdown.clone(),
+        };
+
+        Ok(stream::unfold(subscriber, |mut subscriber| async move {
+            subscriber.next_frame().await.map(|frame| (Ok(frame), subscriber))
+        }))
+    }
+}
+
+struct Subscriber {
+    pool: Pool,
+    receiver: broadcast::Receiver<StreamEvent>,
+    // Newest event sent to the client
+    last_event_id: i64,
+    // Set when events have to be read from the database instead of the receiver
+    catching_up: bool,
+    backlog: VecDeque<StreamEvent>,
+    shutdown: CancellationToken,
+}
+
+impl Subscriber {
+    /// None ends the stream. The client reconnects and resumes from the last event it got.
+    async fn next_frame(&mut self) -> Option<Bytes> {
+        loop {
+            if let Some(event) = self.backlog.pop_front() {
+                self.last_event_id = event.event_id;
+                match event.frame {
+                    Some(frame) => return Some(frame),
+                    None => continue,
+                }
+            }
+
+            if self.catching_up {
+                let client = self.pool.get().await.ok()?;
+                let events = db::posts::get_post_events(&client, self.last_event_id, EVENT_BATCH_SIZE).await.ok()?;
+                self.catching_up = events.len() as i64 == EVENT_BATCH_SIZE;
+                self.backlog.extend(events.iter().map(StreamEvent::from));
+                continue;
+            }
+
+            tokio::select! {
+                received = self.receiver.recv() => match received {
+                    Ok(event) if event.event_id > self.last_event_id => self.backlog.push_back(event),
+                    Ok(_) => {},
+                    // The client reads slower than posts are made
+                    Err(RecvError::Lagged(_)) => self.catching_up = true,
+                    Err(RecvError::Closed) => return None,
+                },
+
+                _ = tokio::time::sleep(KEEP_ALIVE_INTERVAL) => {
+                    return Some(Bytes::from_static(b": keep-alive\n\n"));
+                }
+
+                _ = self.shutdown.cancelled() => {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+pub fn listen_post_events(config: Config, post_stream: PostStream) -> (tokio::task::JoinHandle<Result<(), PoolError>>, CancellationToken) {
+    let cancel_token = CancellationToken::new();
+
+    (
+        tokio::spawn(listen_job(config, post_stream, cancel_token.clone())),
+        cancel_token
+    )
+}
+
+async fn listen_job(config: Config, post_stream: PostStream, cancel_token: CancellationToken) -> Result<(), PoolError> {
+    let mgr_config = ManagerConfig {
+        recycling_method: RecyclingMethod::Fast
+    };
+    let mgr = Manager::from_config(config.clone(), NoTls, mgr_config);
+    let pool = Pool::builder(mgr).max_size(1).build().unwrap();
+
+    let mut last_event_id = None;
+    let mut cleanup = tokio::time::interval(Duration::from_secs(60 * 60));
+
+    loop {
+        // The listener connection is reopened if it is lost
+        match Listener::connect(&config, "LISTEN post_events").await {
+            Ok(listener) => {
+                // Picks up the events committed while the connection was down
+                publish_events(&pool, &post_stream, &mut last_event_id).await?;
+
+                loop {
+                    tokio::select! {
+                        _ = listener.notified() => {
+                            publish_events(&pool, &post_stream, &mut last_event_id).await?;
+                        }
+
+                        _ = cleanup.tick() => {
+                            let _ = db::posts::delete_old_post_events(&pool.get().await?).await;
+                        }
+
+                        _ = listener.closed() => {
+                            break;
+                        }
+
+                        _ = cancel_token.cancelled() => {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            Err(err) => debug!("Failed to listen for post events. {}", err),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {
+                continue;
+            }
+
+            _ = cancel_token.cancelled() => {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends the events after `last_event_id` to the open streams.
+/// The events are read from the table instead of the notification payloads so that none are lost while reconnecting.
+async fn publish_events(pool: &Pool, post_stream: &PostStream, last_event_id: &mut Option<i64>) -> Result<(), PoolError> {
+    let client = pool.get().await?;
+
+    let mut after = match last_event_id {
+        Some(id) => *id,
+        // Started just now, only newer events are sent
+        None => {
+            *last_event_id = db::posts::get_last_post_event_id(&client).await.ok();
+            return Ok(());
+        }
+    };
+
+    loop {
+        let events = match db::posts::get_post_events(&client, after, EVENT_BATCH_SIZE).await {
+            Ok(events) => events,
+            Err(_) => return Ok(()),
+        };
+
+        for event in &events {
+            after = event.event_id;
+            // Fails only if no stream is open
+            let _ = post_stream.sender.send(StreamEvent::from(event));
+        }
+        *last_event_id = Some(after);
+
+        if (events.len() as i64) < EVENT_BATCH_SIZE {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn formats_deleted_events() {
+        let post_id = Uuid::new_v4();
+        let event = StreamEvent::from(&PostEvent { event_id: 7, kind: PostEventKind::Deleted, post_id, post: None });
+
+        assert_eq!(event.event_id, 7);
+        assert_eq!(event.frame.unwrap(), format!("id: 7\nevent: deleted\ndata: {{\"postId\":\"{}\"}}\n\n", post_id));
+    }
+
+    #[test]
+    fn skips_created_events_without_post() {
+        let event = StreamEvent::from(&PostEvent { event_id: 8, kind: PostEventKind::Created, post_id: Uuid::new_v4(), post: None });
+
+        assert_eq!(event.event_id, 8);
+        assert!(event.frame.is_none());
+    }
+
+    #[test]
+    fn formats_reset_events() {
+        let event = StreamEvent::reset(42);
+
+        assert_eq!(event.event_id, 42);
+        assert_eq!(event.frame.unwrap(), "id: 42\nevent: reset\ndata: {}\n\n");
+    }
+}
@@ -0,0 +1,88 @@
+use std::net::IpAddr;
+
+/// Reverse proxies whose X-Forwarded-For header is trusted. The server listens on localhost only,
+/// so in a deployment every request comes through a proxy and its address is not the client's.
+#[derive(Clone, Default)]
+pub struct TrustedProxies {
+    addresses: Vec<IpAddr>,
+}
+
+impl TrustedProxies {
+    /// Parses a comma separated list of ip addresses, such as the TRUSTED_PROXIES environment variable.
+    pub fn parse(list: &str) -> Result<TrustedProxies, std::net::AddrParseError> {
+        let addresses = list.split(',')
+            .map(str::trim)
+            .filter(|address| !address.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<IpAddr>, _>>()?;
+
+        Ok(TrustedProxies { addresses })
+    }
+
+    fn is_trusted(&self, address: &IpAddr) -> bool {
+        self.addresses.contains(address)
+    }
+
+    /// Resolves the address of the client. If the connection comes from a trusted proxy, the forwarded addresses
+    /// are read from the right, skipping other trusted proxies, since the ones further left can be set by the client.
+    /// None if there is no connection address, or if a trusted proxy did not forward a valid one.
+    pub fn client_ip(&self, peer: Option<IpAddr>, forwarded_for: &[&str]) -> Option<IpAddr> {
+        let peer = peer?;
+        if !self.is_trusted(&peer) {
+            return Some(peer)
+        }
+
+        for address in forwarded_for.iter().rev().flat_map(|header| header.rsplit(',')) {
+            let address: IpAddr = address.trim().parse().ok()?;
+            if !self.is_trusted(&address) {
+                return Some(address)
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(address: &str) -> Option<IpAddr> {
+        Some(address.parse().unwrap())
+    }
+
+    #[test]
+    fn ignores_forwarded_header_from_untrusted_peer() {
+        let proxies = TrustedProxies::parse("127.0.0.1").unwrap();
+
+        assert_eq!(proxies.client_ip(ip("203.0.113.5"), &["198.51.100.1"]), ip("203.0.113.5"));
+        assert_eq!(TrustedProxies::default().client_ip(ip("127.0.0.1"), &["198.51.100.1"]), ip("127.0.0.1"));
+    }
+
+    #[test]
+    fn reads_forwarded_address_from_the_right() {
+        let proxies = TrustedProxies::parse("127.0.0.1, 10.0.0.2").unwrap();
+
+        assert_eq!(proxies.client_ip(ip("127.0.0.1"), &["198.51.100.1"]), ip("198.51.100.1"));
+        // The left entry was sent by the client and is not used
+        assert_eq!(proxies.client_ip(ip("127.0.0.1"), &["1.2.3.4, 198.51.100.1, 10.0.0.2"]), ip("198.51.100.1"));
+        assert_eq!(proxies.client_ip(ip("127.0.0.1"), &["1.2.3.4", "198.51.100.1"]), ip("198.51.100.1"));
+        assert_eq!(proxies.client_ip(ip("127.0.0.1"), &["2001:db8::1"]), ip("2001:db8::1"));
+    }
+
+    #[test]
+    fn unresolved_when_proxy_does_not_forward_a_valid_address() {
+        let proxies = TrustedProxies::parse("127.0.0.1,10.0.0.2").unwrap();
+
+        assert_eq!(proxies.client_ip(ip("127.0.0.1"), &[]), None);
+        assert_eq!(proxies.client_ip(ip("127.0.0.1"), &["10.0.0.2"]), None);
+        assert_eq!(proxies.client_ip(ip("127.0.0.1"), &["1.2.3.4, unknown"]), None);
+        assert_eq!(proxies.client_ip(None, &["198.51.100.1"]), None);
+    }
+
+    #[test]
+    fn rejects_invalid_proxy_list() {
+        assert!(TrustedProxies::parse("").is_ok());
+        assert!(TrustedProxies::parse("127.0.0.1,localhost").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER};
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng as _, rngs::OsRng};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Generates a cryptographically random alphanumeric token of the given length.
+pub fn generate_token(length: usize) -> String {
+    std::iter::repeat(())
+        .map(|()| OsRng.sample(Alphanumeric) as char)
+        .take(length)
+        .collect()
+}
+
+/// Hashes a high entropy token for storage. Tokens are random, so a salt or a slow hash is not needed.
+pub fn hash_token(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+/// Derives a key for a specific purpose from the application secret,
+/// so that tokens signed for one purpose cannot be used for another.
+pub fn derive_key(secret: &[u8], purpose: &str) -> Vec<u8> {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(purpose.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Creates a token containing the payload and its signature.
+/// The payload is not encrypted, so it must not contain secrets.
+pub fn sign_token<T: Serialize>(key: &[u8], payload: &T) -> Result<String, serde_json::Error> {
+    let payload = BASE64URL_NOPAD.encode(&serde_json::to_vec(payload)?);
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(payload.as_bytes());
+
+    Ok(format!("{}.{}", payload, BASE64URL_NOPAD.encode(&mac.finalize().into_bytes())))
+}
+
+/// Payloads of signed tokens that stop being valid at some point
+pub trait Expiring {
+    /// Unix timestamp in seconds
+    fn expires_at(&self) -> i64;
+}
+
+/// Returns the payload of the token if the signature is valid and it has not expired by `now`.
+pub fn verify_unexpired_token<T: DeserializeOwned + Expiring>(key: &[u8], token: &str, now: i64) -> Option<T> {
+    verify_signed_token::<T>(key, token).filter(|payload| payload.expires_at() > now)
+}
+
+/// Returns the payload of the token if the signature is valid.
+pub fn verify_signed_token<T: DeserializeOwned>(key: &[u8], token: &str) -> Option<T> {
+    let (payload, signature) = token.split_once('.')?;
+    let signature = BASE64URL_NOPAD.decode(signature.as_bytes()).ok()?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(payload.as_bytes());
+    // Constant time comparison
+    mac.verify_slice(&signature).ok()?;
+
+    serde_json::from_slice(&BASE64URL_NOPAD.decode(payload.as_bytes()).ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    const SECRET: &[u8] = b"application secret";
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Claims {
+        user: String,
+        expires_at: i64,
+    }
+
+    impl Expiring for Claims {
+        fn expires_at(&self) -> i64 {
+            self.expires_at
+        }
+    }
+
+    fn claims() -> Claims {
+        Claims { user: "alice".to_string(), expires_at: 1000 }
+    }
+
+    #[test]
+    fn signed_tokens_round_trip() {
+        let key = derive_key(SECRET, "test");
+        let token = sign_token(&key, &claims()).unwrap();
+
+        assert_eq!(verify_signed_token::<Claims>(&key, &token), Some(claims()));
+    }
+
+    #[test]
+    fn rejects_tampered_payload() {
+        let key = derive_key(SECRET, "test");
+        let token = sign_token(&key, &claims()).unwrap();
+        let (_, signature) = token.split_once('.').unwrap();
+
+        let forged = BASE64URL_NOPAD.encode(&serde_json::to_vec(&Claims { user: "mallory".to_string(), expires_at: 1000 }).unwrap());
+        assert_eq!(verify_signed_token::<Claims>(&key, &format!("{}.{}", forged, signature)), None);
+    }
+
+    #[test]
+    fn rejects_tampered_or_truncated_signature() {
+        let key = derive_key(SECRET, "test");
+        let token = sign_token(&key, &claims()).unwrap();
+        let (payload, signature) = token.split_once('.').unwrap();
+
+        let mut tampered = BASE64URL_NOPAD.decode(signature.as_bytes()).unwrap();
+        tampered[0] ^= 1;
+        assert_eq!(verify_signed_token::<Claims>(&key, &format!("{}.{}", payload, BASE64URL_NOPAD.encode(&tampered))), None);
+
+        let truncated = BASE64URL_NOPAD.encode(&BASE64URL_NOPAD.decode(signature.as_bytes()).unwrap()[..16]);
+        assert_eq!(verify_signed_token::<Claims>(&key, &format!("{}.{}", payload, truncated)), None);
+        assert_eq!(verify_signed_token::<Claims>(&key, &format!("{}.", payload)), None);
+        assert_eq!(verify_signed_token::<Claims>(&key, payload), None);
+    }
+
+    #[test]
+    fn rejects_expired_tokens() {
+        let key = derive_key(SECRET, "test");
+        let token = sign_token(&key, &claims()).unwrap();
+
+        assert_eq!(verify_unexpired_token::<Claims>(&key, &token, 999), Some(claims()));
+        assert_eq!(verify_unexpired_token::<Claims>(&key, &token, 1000), None);
+        assert_eq!(verify_unexpired_token::<Claims>(&key, &token, 2000), None);
+    }
+
+    #[test]
+    fn rejects_tokens_signed_for_another_purpose() {
+        let token = sign_token(&derive_key(SECRET, "password-reset"), &claims()).unwrap();
+
+        assert_ne!(derive_key(SECRET, "password-reset"), derive_key(SECRET, "email-verification"));
+        assert_eq!(verify_signed_token::<Claims>(&derive_key(SECRET, "email-verification"), &token), None);
+        assert_eq!(verify_signed_token::<Claims>(&derive_key(b"other secret", "password-reset"), &token), None);
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use rand::{RngCore, rngs::OsRng};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+// Parameters from RFC 6238. These are the defaults that authenticator apps assume.
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_LENGTH: usize = 20;
+// Number of steps before and after the current one that are accepted to allow for clock drift
+const ALLOWED_DRIFT: u64 = 1;
+const ISSUER: &str = "Secure Programming";
+
+/// Generates a new base32 encoded TOTP secret.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// Generates the otpauth uri that authenticator apps read from a QR code.
+pub fn provisioning_uri(secret: &str, account: &str) -> String {
+    let label = format!("{}:{}", ISSUER, account);
+
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        utf8_percent_encode(&label, NON_ALPHANUMERIC),
+        secret,
+        utf8_percent_encode(ISSUER, NON_ALPHANUMERIC),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+fn current_step() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    now / STEP_SECONDS
+}
+
+/// Dynamically truncated HMAC-SHA1 value as defined in RFC 4226, before it is reduced to the number of digits.
+fn truncated_hmac(secret: &[u8], counter: u64) -> u32 {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+
+    u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]]) & 0x7fff_ffff
+}
+
+/// HOTP value as defined in RFC 4226.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    truncated_hmac(secret, counter) % 10u32.pow(DIGITS)
+}
+
+/// Checks the code against the steps around the current time.
+/// Returns the step that matched so that it can be marked as used to prevent replaying the same code.
+pub fn verify_code(secret: &str, code: &str) -> Option<i64> {
+    verify_code_at(secret, code, current_step())
+}
+
+fn verify_code_at(secret: &str, code: &str, step: u64) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None
+    }
+
+    let mut matched = None;
+
+    // All steps are checked so that the time taken does not depend on which one matched
+    for candidate in step.saturating_sub(ALLOWED_DRIFT)..=step + ALLOWED_DRIFT {
+        let expected = format!("{:0width$}", hotp(&secret, candidate), width = DIGITS as usize);
+        if bool::from(expected.as_bytes().ct_eq(code.as_bytes())) {
+            matched = Some(candidate as i64);
+        }
+    }
+
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Secret of the test vectors in RFC 4226 and RFC 6238
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn code(secret: &[u8], step: u64) -> String {
+        format!("{:06}", hotp(secret, step))
+    }
+
+    #[test]
+    fn matches_rfc_4226_vectors() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+
+        for (counter, value) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as u64), value, "counter {}", counter);
+        }
+    }
+
+    #[test]
+    fn matches_rfc_6238_sha1_vectors() {
+        let expected = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+
+        for (time, value) in expected {
+            // The RFC lists 8 digit codes
+            assert_eq!(truncated_hmac(RFC_SECRET, time / STEP_SECONDS) % 100_000_000, value, "time {}", time);
+        }
+    }
+
+    #[test]
+    fn accepts_steps_within_drift() {
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+        let step = 1_000_000;
+
+        for candidate in step - ALLOWED_DRIFT..=step + ALLOWED_DRIFT {
+            assert_eq!(verify_code_at(&secret, &code(RFC_SECRET, candidate), step), Some(candidate as i64));
+        }
+    }
+
+    #[test]
+    fn rejects_steps_outside_drift() {
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+        let step = 1_000_000;
+
+        assert_eq!(verify_code_at(&secret, &code(RFC_SECRET, step - ALLOWED_DRIFT - 1), step), None);
+        assert_eq!(verify_code_at(&secret, &code(RFC_SECRET, step + ALLOWED_DRIFT + 1), step), None);
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+
+        assert_eq!(verify_code_at(&secret, "12345", 0), None);
+        assert_eq!(verify_code_at(&secret, "7552a4", 0), None);
+        assert_eq!(verify_code_at("not base32!", "755224", 0), None);
+        assert_eq!(verify_code_at(&secret, " 755224 ", 0), Some(0));
+    }
+
+    #[test]
+    fn escapes_provisioning_uri_label() {
+        let uri = provisioning_uri("JBSWY3DPEHPK3PXP", "a b&c+d@example.com");
+
+        assert_eq!(
+            uri,
+            "otpauth://totp/Secure%20Programming%3Aa%20b%26c%2Bd%40example%2Ecom?secret=JBSWY3DPEHPK3PXP\
+            &issuer=Secure%20Programming&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use webauthn_rs::prelude::{AuthenticationResult, Url, Uuid as WebauthnUuid, WebauthnError};
+use webauthn_rs::{Webauthn, WebauthnBuilder};
+
+const RP_NAME: &str = "Secure Programming";
+
+/// Creates the relying party configuration. The relying party id is the host of the origin,
+/// so credentials are bound to the domain the frontend is served from.
+pub fn build_webauthn(origin: &Url) -> Result<Webauthn, WebauthnError> {
+    let rp_id = origin.host_str().ok_or(WebauthnError::Configuration)?;
+
+    WebauthnBuilder::new(rp_id, origin)?
+        .rp_name(RP_NAME)
+        .build()
+}
+
+/// webauthn-rs uses a newer version of the uuid crate than the rest of the application
+pub fn to_webauthn_uuid(user_id: &uuid::Uuid) -> WebauthnUuid {
+    WebauthnUuid::from_bytes(*user_id.as_bytes())
+}
+
+pub fn from_webauthn_uuid(user_id: &WebauthnUuid) -> uuid::Uuid {
+    uuid::Uuid::from_bytes(*user_id.as_bytes())
+}
+
+/// Checks the signature counter of an assertion against the stored value.
+/// Authenticators that do not implement a counter always report 0. For the others
+/// the counter must increase on every use, otherwise the credential might have been cloned.
+pub fn counter_is_valid(stored_counter: u32, result: &AuthenticationResult) -> bool {
+    let counter = result.counter();
+    (counter == 0 && stored_counter == 0) || counter > stored_counter
+}
+
+#[cfg(test)]
+mod tests {
+    use webauthn_authenticator_rs::softpasskey::SoftPasskey;
+    use webauthn_authenticator_rs::WebauthnAuthenticator;
+
+    use super::*;
+
+    fn setup() -> (Webauthn, Url, WebauthnAuthenticator<SoftPasskey>) {
+        let origin = Url::parse("http://localhost:8080").unwrap();
+        let webauthn = build_webauthn(&origin).unwrap();
+        (webauthn, origin, WebauthnAuthenticator::new(SoftPasskey::new(true)))
+    }
+
+    #[test]
+    fn uuid_conversion_round_trips() {
+        let user_id = uuid::Uuid::new_v4();
+        assert_eq!(from_webauthn_uuid(&to_webauthn_uuid(&user_id)), user_id);
+    }
+
+    #[test]
+    fn rejects_origin_without_host() {
+        let origin = Url::parse("data:text/plain,test").unwrap();
+        assert!(build_webauthn(&origin).is_err());
+    }
+
+    #[test]
+    fn register_and_authenticate_with_software_authenticator() {
+        let (webauthn, origin, mut authenticator) = setup();
+        let user_id = to_webauthn_uuid(&uuid::Uuid::new_v4());
+
+        let (challenge, registration) = webauthn
+            .start_passkey_registration(user_id, "user@example.com", "user", None)
+            .unwrap();
+        let credential = authenticator.do_registration(origin.clone(), challenge).unwrap();
+        let mut passkey = webauthn.finish_passkey_registration(&credential, &registration).unwrap();
+
+        let mut stored_counter = 0;
+        for _ in 0..2 {
+            let (challenge, authentication) = webauthn
+                .start_passkey_authentication(std::slice::from_ref(&passkey))
+                .unwrap();
+            let assertion = authenticator.do_authentication(origin.clone(), challenge).unwrap();
+            let result = webauthn.finish_passkey_authentication(&assertion, &authentication).unwrap();
+
+            assert_eq!(result.cred_id(), passkey.cred_id());
+            assert!(counter_is_valid(stored_counter, &result));
+            assert!(!counter_is_valid(result.counter(), &result));
+
+            passkey.update_credential(&result);
+            stored_counter = result.counter();
+        }
+    }
+
+    #[test]
+    fn assertion_is_bound_to_its_challenge() {
+        let (webauthn, origin, mut authenticator) = setup();
+        let user_id = to_webauthn_uuid(&uuid::Uuid::new_v4());
+
+        let (challenge, registration) = webauthn
+            .start_passkey_registration(user_id, "user@example.com", "user", None)
+            .unwrap();
+        let credential = authenticator.do_registration(origin.clone(), challenge).unwrap();
+        let passkey = webauthn.finish_passkey_registration(&credential, &registration).unwrap();
+
+        let (first_challenge, _) = webauthn.start_passkey_authentication(std::slice::from_ref(&passkey)).unwrap();
+        let (_, second_authentication) = webauthn.start_passkey_authentication(&[passkey]).unwrap();
+
+        let assertion = authenticator.do_authentication(origin, first_challenge).unwrap();
+        assert!(webauthn.finish_passkey_authentication(&assertion, &second_authentication).is_err());
+    }
+}
@@ -13,11 +13,11 @@ import { appPath } from '@/utils/constants';
  * Shown actions depend on whether the user is logged in or not.
  */
 export const NavBar: FC = () => {
-  const { isAuthenticated, user, setUser, isAdmin } = useUser();
+  const { isAuthenticated, user, setUser } = useUser();
   const csrf = useCSRF();
   const queryClient = useQueryClient();
   const router = useRouter();
-  const adminSuffix = isAdmin ? ' (admin)' : '';
+  const roleSuffix = user && user.role !== 'user' ? ` (${user.role})` : '';
 
   const logoutUser = (event: SyntheticEvent) => {
     event.preventDefault();
@@ -58,7 +58,7 @@ export const NavBar: FC = () => {
           { isAuthenticated ? (
             <>
               <Typography variant='body2' sx={{ mr: 2 }}>
-                Logged in as {user?.username}{adminSuffix}
+                Logged in as {user?.username}{roleSuffix}
               </Typography>
               <Button color='inherit' href='/profile' component={Link}>Profile</Button>
               <Button color='inherit' onClick={logoutUser}>Logout</Button>
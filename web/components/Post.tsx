@@ -21,16 +21,17 @@ import { handleResponse } from '@/types/api/utilities';
 
 /**
  * Post component which shows a user's filled text along with the user's name and the time of the post's publication.
- * Only the user who created the post, and an admin, can see the delete button and delete the post.
+ * Only the user who created the post, and users with the delete_post permission, can see the delete button and delete the post.
  */
-export const Post: FC<PostProps> = ({ user, text, timestamp, postId }) => {
-  const { user: currentUser } = useUser();
+export const Post: FC<PostProps> = ({ user, text, timestamp, editedAt, postId }) => {
+  const { user: currentUser, hasPermission } = useUser();
+  const canDeleteAny = hasPermission('delete_post');
   const csrf = useCSRF();
   const queryClient = useQueryClient();
   const [open, setOpen] = useState(false);
 
   const deletePost = () => {
-    const suffix = currentUser?.admin ? `/admin` : ``;
+    const suffix = canDeleteAny ? `/admin` : ``;
     fetch(`${appPath}/api/posts/delete/${postId}${suffix}`, {
       method: 'DELETE',
       credentials: 'include',
@@ -62,8 +63,8 @@ export const Post: FC<PostProps> = ({ user, text, timestamp, postId }) => {
     <>
       <Card sx={{ width: 500 }}>
         <CardHeader
-          title={user.username}
-          subheader={formatTimestamp(timestamp)}
+          title={`${user.username} @${user.handle}`}
+          subheader={formatTimestamp(timestamp) + (editedAt ? ' (edited)' : '')}
         />
         <CardContent>
           <Typography variant='body2' color='inherit'>
@@ -71,7 +72,7 @@ export const Post: FC<PostProps> = ({ user, text, timestamp, postId }) => {
           </Typography>
         </CardContent>
         <CardActions>
-          {(currentUser?.userId === user.userId || currentUser?.admin) && (
+          {(currentUser?.userId === user.userId || canDeleteAny) && (
             <Button
               size='small'
               color='primary'
@@ -8,29 +8,27 @@ import {
   Typography,
 } from '@mui/material';
 import { FC, FormEvent, useEffect, useRef, useState } from 'react';
-import { useQueryClient } from '@tanstack/react-query';
 import { useRouter } from 'next/router';
 import { NavBar } from '@/components/NavBar';
 import Link from '@/components/Link';
-import { csrfHeader, invalidateCsrfToken, useCSRF } from '@/utils/useCsrf';
+import { csrfHeader, useCSRF } from '@/utils/useCsrf';
 import { handleResponse } from '@/types/api/utilities';
-import { FrontendUser } from '@/types/api/user';
 import { useUser } from '@/utils/useUser';
 import { PasswordField } from '@/components/PasswordField';
 import { appPath } from '@/utils/constants';
 
 
 /**
- * Form for signing up a user. A username, password, and a unique email are required.
+ * Form for signing up a user. A username, handle, password, and an email are required.
+ * The account is activated through a link sent to the email.
  * The sig nup button is disabled if the user is already logged in or the user's account is being fetched.
  */
 const SignUpForm: FC = () => {
   const PWRef = useRef<HTMLInputElement>();
   const [alert, setAlert] = useState('');
+  const [info, setInfo] = useState('');
   const csrf = useCSRF();
-  const queryClient = useQueryClient();
-  const { setUser, isLoading, isAuthenticated } = useUser();
-  const router = useRouter();
+  const { isLoading, isAuthenticated } = useUser();
 
   const signUpUser = (event: FormEvent<HTMLFormElement>) => {
     event.preventDefault();
@@ -49,16 +47,17 @@ const SignUpForm: FC = () => {
         ...csrfHeader(csrf),
       },
       body: JSON.stringify(body),
-    }).then(handleResponse<FrontendUser>('user'))
-      .then(setUser)
-      .then(() => invalidateCsrfToken(queryClient))
-      .then(() => {
+    }).then(handleResponse<string>('message'))
+      .then((message) => {
         setAlert('');
-        return router.push('/');
+        setInfo(message);
       })
       .catch((e) => {
-        PWRef.current!.value = '';
+        setInfo('');
         setAlert(e.message);
+      })
+      .finally(() => {
+        PWRef.current!.value = '';
       });
   };
 
@@ -86,6 +85,14 @@ const SignUpForm: FC = () => {
           Sign up failed; {alert}
         </Alert>
       ) : null}
+      {info ? (
+        <Alert
+          severity='success'
+          sx={{ margin: 'auto', mb: 2 }}
+        >
+          {info}
+        </Alert>
+      ) : null}
       <TextField
         required
         InputLabelProps={{ required: false }}
@@ -94,6 +101,15 @@ const SignUpForm: FC = () => {
         inputProps={{ maxLength: 32 }}
         sx={{ mb: 2 }}
       />
+      <TextField
+        required
+        InputLabelProps={{ required: false }}
+        name='handle'
+        label='Handle'
+        helperText='3-30 letters, numbers or underscores'
+        inputProps={{ maxLength: 30, pattern: '@?[A-Za-z0-9_]{3,30}' }}
+        sx={{ mb: 2 }}
+      />
       <PasswordField
         strict
         meter
@@ -1,6 +1,24 @@
+export type PostEntity =
+  | { type: 'mention', start: number, end: number, handle: string, userId: string }
+  | { type: 'hashtag', start: number, end: number, tag: string };
+
+export type PostReaction = {
+  reaction: string,
+  count: number,
+  reactedByMe: boolean,
+}
+
 export type Post = {
-  user: {username: string, userId: string},
+  user: {username: string, handle: string, userId: string},
   postId: string,
   timestamp: string,
+  editedAt: string | null,
   text: string,
+  parentPostId: string | null,
+  rootPostId: string | null,
+  replyCount: number,
+  // Ranges count UTF-16 code units, so they can be used with String.prototype.slice
+  entities: PostEntity[],
+  // Most used first
+  reactions: PostReaction[],
 }
@@ -1,5 +1,7 @@
 export type FrontendUser = {
   userId: string,
   username: string,
-  admin: boolean,
+  handle: string,
+  role: string,
+  permissions: string[],
 }
@@ -11,7 +11,7 @@ export type UserContextValue = {
 
 export type UseUser = () => UserContextValue & {
   isAuthenticated: boolean
-  isAdmin: boolean
+  hasPermission: (permission: string) => boolean
   setUser: (user: FrontendUser | null | undefined) => void
 }
 
@@ -26,11 +26,16 @@ export const useUser: UseUser = () => {
     client.setQueryData(QueryKeys.user, newUser);
   }, [client]);
 
+  const hasPermission = useCallback(
+    (permission: string) => !!user?.permissions.includes(permission),
+    [user]
+  );
+
   return useMemo(() => ({
     user,
     isAuthenticated: !!user,
-    isAdmin: !!user?.admin,
+    hasPermission,
     isLoading,
     setUser,
-  }), [user, isLoading, setUser]);
+  }), [user, isLoading, setUser, hasPermission]);
 };